@@ -0,0 +1,70 @@
+//! Simulation of several environments in lockstep.
+
+use alloc::vec::Vec;
+
+use crate::{Environment, GrabError, GrabberId, Transition};
+
+/// Stores environments that are simulated in lockstep,
+/// e.g. for parallel rollouts of policies.
+#[derive(Clone, Default)]
+pub struct Batch {
+    /// The environments.
+    pub envs: Vec<Environment>,
+}
+
+impl Batch {
+    /// Creates a new batch of environments.
+    pub fn new(envs: Vec<Environment>) -> Batch {
+        Batch {envs}
+    }
+
+    /// Updates every environment with the same time delta.
+    ///
+    /// Returns the transitions of each environment, see `Environment::update`.
+    pub fn step_all(&mut self, dt: f64) -> Vec<Vec<(GrabberId, Transition)>> {
+        self.envs.iter_mut().map(|env| env.update(dt)).collect()
+    }
+
+    /// Activates one grabber per environment, if any.
+    ///
+    /// Returns the result of each activation, or `None` where no grabber was given.
+    /// Panics if the number of actions does not match the number of environments.
+    pub fn grab_all_batch(
+        &mut self,
+        actions: &[Option<GrabberId>]
+    ) -> Vec<Option<Result<(), GrabError>>> {
+        assert_eq!(actions.len(), self.envs.len(), "Expected one action per environment");
+        self.envs.iter_mut().zip(actions.iter())
+            .map(|(env, action)| action.map(|gid| env.grab(gid)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_batch() {
+        let mut envs = vec![];
+        for &volume in &[1.0, 3.0, 5.0] {
+            let mut env = Environment::new();
+            let a = env.add_container(Container::new(volume));
+            let b = env.add_container(Container::new(0.0));
+            env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+            envs.push(env);
+        }
+        let mut batch = Batch::new(envs);
+        let ab = GrabberId(0);
+        assert_eq!(batch.grab_all_batch(&[Some(ab), None, Some(ab)]),
+                   vec![Some(Ok(())), None, Some(Ok(()))]);
+        assert_eq!(batch.grab_all_batch(&[Some(ab), None, None]),
+                   vec![Some(Err(GrabError::Busy)), None, None]);
+        let transitions = batch.step_all(1.0);
+        assert_eq!(transitions[0], vec![(ab, Transition::Finished)]);
+        assert!(transitions[1].is_empty());
+        let volumes: Vec<f64> = batch.envs.iter()
+            .map(|env| env.volume_of_container(ContainerId(1))).collect();
+        assert_eq!(volumes, vec![1.0, 0.0, 2.0]);
+    }
+}
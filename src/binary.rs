@@ -0,0 +1,534 @@
+//! Compact binary encoding of environments.
+//!
+//! The layout starts with a header of 4 magic bytes followed by
+//! the format version as a little-endian `u32`.
+//! Lists are prefixed by their length as `u64`,
+//! numbers are stored little-endian.
+//!
+//! Only the model, its simulation state and statistics are encoded.
+//! Instrumentation such as provenance tracking is not.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::{
+    Condition,
+    Container,
+    ContainerId,
+    DeliveryMode,
+    Environment,
+    Grabber,
+    GrabberId,
+    GrabberState,
+};
+
+/// The magic bytes at the start of an encoded environment.
+const MAGIC: &[u8; 4] = b"DIGE";
+
+/// The version of the binary format.
+///
+/// Bumped whenever the layout changes after a release.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Describes an error when decoding an environment.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The data does not start with the magic bytes.
+    InvalidHeader,
+    /// The data was encoded with an unsupported format version.
+    UnsupportedVersion(u32),
+    /// The data ended unexpectedly.
+    UnexpectedEnd,
+    /// The data contains an invalid value.
+    InvalidValue,
+    /// The data refers to a container that does not exist.
+    InvalidContainerId(usize),
+    /// There are bytes left after the environment.
+    TrailingBytes,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidHeader => write!(f, "Invalid header"),
+            DecodeError::UnsupportedVersion(v) =>
+                write!(f, "Unsupported format version {}", v),
+            DecodeError::UnexpectedEnd => write!(f, "Unexpected end of data"),
+            DecodeError::InvalidValue => write!(f, "Invalid value"),
+            DecodeError::InvalidContainerId(id) =>
+                write!(f, "Invalid container ID {}", id),
+            DecodeError::TrailingBytes => write!(f, "Trailing bytes after environment"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for DecodeError {}
+
+/// Writes values to a byte buffer.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {self.0.push(v)}
+    fn bool(&mut self, v: bool) {self.u8(v as u8)}
+    fn u32(&mut self, v: u32) {self.0.extend_from_slice(&v.to_le_bytes())}
+    fn u64(&mut self, v: u64) {self.0.extend_from_slice(&v.to_le_bytes())}
+    fn f64(&mut self, v: f64) {self.0.extend_from_slice(&v.to_le_bytes())}
+    fn len(&mut self, n: usize) {self.u64(n as u64)}
+    fn container_id(&mut self, id: ContainerId) {self.u64(id.0 as u64)}
+    fn grabber_id(&mut self, id: GrabberId) {self.u64(id.0 as u64)}
+
+    fn string(&mut self, s: &str) {
+        self.len(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn option_string(&mut self, s: &Option<String>) {
+        match s {
+            None => self.u8(0),
+            Some(s) => {
+                self.u8(1);
+                self.string(s);
+            }
+        }
+    }
+
+    fn option_f64(&mut self, v: Option<f64>) {
+        match v {
+            None => self.u8(0),
+            Some(v) => {
+                self.u8(1);
+                self.f64(v);
+            }
+        }
+    }
+}
+
+/// Reads values from a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.data.len() - self.pos < n {return Err(DecodeError::UnexpectedEnd)}
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut arr = [0; N];
+        arr.copy_from_slice(self.bytes(N)?);
+        Ok(arr)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {Ok(self.bytes(1)?[0])}
+    fn u32(&mut self) -> Result<u32, DecodeError> {Ok(u32::from_le_bytes(self.array()?))}
+    fn u64(&mut self) -> Result<u64, DecodeError> {Ok(u64::from_le_bytes(self.array()?))}
+    fn f64(&mut self) -> Result<f64, DecodeError> {Ok(f64::from_le_bytes(self.array()?))}
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let n = self.u64()?;
+        // Every item takes at least one byte, which guards against huge allocations.
+        if n > (self.data.len() - self.pos) as u64 {return Err(DecodeError::UnexpectedEnd)}
+        Ok(n as usize)
+    }
+
+    fn container_id(&mut self, n: usize) -> Result<ContainerId, DecodeError> {
+        let id = self.u64()?;
+        if id >= n as u64 {return Err(DecodeError::InvalidContainerId(id as usize))}
+        Ok(ContainerId(id as usize))
+    }
+
+    fn grabber_id(&mut self, m: usize) -> Result<GrabberId, DecodeError> {
+        let id = self.u64()?;
+        if id >= m as u64 {return Err(DecodeError::InvalidValue)}
+        Ok(GrabberId(id as usize))
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.len()?;
+        let s = core::str::from_utf8(self.bytes(len)?).map_err(|_| DecodeError::InvalidValue)?;
+        Ok(s.into())
+    }
+
+    fn option_string(&mut self) -> Result<Option<String>, DecodeError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.string()?)),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+
+    fn option_f64(&mut self) -> Result<Option<f64>, DecodeError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.f64()?)),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+
+    fn delivery(&mut self) -> Result<DeliveryMode, DecodeError> {
+        match self.u8()? {
+            0 => Ok(DeliveryMode::Lump),
+            1 => Ok(DeliveryMode::Continuous),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+}
+
+impl Environment {
+    /// Encodes the environment in a compact binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(vec![]);
+        w.0.extend_from_slice(MAGIC);
+        w.u32(FORMAT_VERSION);
+        w.len(self.containers.len());
+        for (c, &pending) in self.containers.iter().zip(self.pending.iter()) {
+            w.f64(c.volume);
+            w.f64(pending);
+            w.bool(c.sink);
+            w.bool(c.fixed);
+            w.f64(c.height);
+            w.f64(c.leak_rate);
+            w.f64(c.capacity);
+            w.option_string(&c.unit);
+            w.f64(c.unit_scale);
+            w.f64(c.position[0]);
+            w.f64(c.position[1]);
+        }
+        for i in 0..self.containers.len() {
+            w.bool(self.removed[i]);
+            w.f64(self.peaks[i]);
+            w.f64(self.sunk[i]);
+        }
+        match &self.allowed_transfers {
+            None => w.u8(0),
+            Some(allowed) => {
+                w.u8(1);
+                w.len(allowed.len());
+                for &(source, target) in allowed {
+                    w.container_id(source);
+                    w.container_id(target);
+                }
+            }
+        }
+        w.len(self.grabbers.len());
+        for (g, s) in self.grabbers.iter().zip(self.grabber_states.iter()) {
+            w.f64(g.volume);
+            w.f64(g.time);
+            w.container_id(g.source);
+            w.container_id(g.target);
+            w.u8(match g.delivery {
+                DeliveryMode::Lump => 0,
+                DeliveryMode::Continuous => 1,
+            });
+            w.f64(g.jitter);
+            w.f64(g.cooldown);
+            match g.condition {
+                None => w.u8(0),
+                Some(Condition::Below(c, v)) => {
+                    w.u8(1);
+                    w.container_id(c);
+                    w.f64(v);
+                }
+                Some(Condition::Above(c, v)) => {
+                    w.u8(2);
+                    w.container_id(c);
+                    w.f64(v);
+                }
+            }
+            w.option_f64(g.batch_size);
+            w.bool(g.require_space);
+            w.bool(g.auto);
+            w.f64(g.reaction_rate);
+            w.f64(g.wear_factor);
+            w.f64(g.min_volume);
+            w.len(g.stages);
+            w.f64(g.ramp);
+            match g.demand_threshold {
+                None => w.u8(0),
+                Some((c, level)) => {
+                    w.u8(1);
+                    w.container_id(c);
+                    w.f64(level);
+                }
+            }
+            w.f64(s.time);
+            w.f64(s.volume);
+            w.f64(s.moved);
+            w.f64(s.cooldown);
+            w.f64(s.duration);
+            w.u64(s.activations);
+            w.u64(s.streak);
+            w.f64(s.idle_since);
+            w.bool(s.frozen);
+        }
+        w.len(self.names.len());
+        for (name, c) in &self.names {
+            w.string(name);
+            w.container_id(*c);
+        }
+        w.len(self.links.len());
+        for group in &self.links {
+            w.len(group.len());
+            for &gid in group {w.grabber_id(gid)}
+        }
+        w.len(self.shared.len());
+        for group in &self.shared {
+            w.len(group.len());
+            for &gid in group {w.grabber_id(gid)}
+        }
+        w.option_f64(self.saturation);
+        w.f64(self.gravity);
+        w.option_f64(self.delivery_cap);
+        match self.fallback {
+            None => w.u8(0),
+            Some(c) => {
+                w.u8(1);
+                w.container_id(c);
+            }
+        }
+        w.u64(self.rng.state);
+        w.u64(self.sensor_rng.state);
+        w.f64(self.total_leaked);
+        w.f64(self.total_work);
+        w.f64(self.exchange.added);
+        w.f64(self.exchange.removed);
+        w.f64(self.time);
+        w.len(self.inflows.len());
+        for &(at, c, amount) in &self.inflows {
+            w.f64(at);
+            w.container_id(c);
+            w.f64(amount);
+        }
+        w.0
+    }
+
+    /// Decodes an environment from the binary format produced by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> Result<Environment, DecodeError> {
+        let mut r = Reader {data, pos: 0};
+        if r.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(DecodeError::InvalidHeader);
+        }
+        let version = r.u32()?;
+        if version != FORMAT_VERSION {return Err(DecodeError::UnsupportedVersion(version))}
+
+        let mut env = Environment::new();
+        let n = r.len()?;
+        for _ in 0..n {
+            let mut c = Container::new(r.f64()?);
+            let pending = r.f64()?;
+            c.sink = r.bool()?;
+            c.fixed = r.bool()?;
+            c.height = r.f64()?;
+            c.leak_rate = r.f64()?;
+            c.capacity = r.f64()?;
+            c.unit = r.option_string()?;
+            c.unit_scale = r.f64()?;
+            c.position = [r.f64()?, r.f64()?];
+            let id = env.add_container(c);
+            env.pending[id.0] = pending;
+        }
+        for i in 0..n {
+            env.removed[i] = r.bool()?;
+            env.peaks[i] = r.f64()?;
+            env.sunk[i] = r.f64()?;
+        }
+        // The allow-list is applied after the grabbers,
+        // since grabbers added before restricting transfers stay valid.
+        let allowed_transfers = match r.u8()? {
+            0 => None,
+            1 => {
+                let k = r.len()?;
+                let mut allowed = Vec::with_capacity(k);
+                for _ in 0..k {
+                    allowed.push((r.container_id(n)?, r.container_id(n)?));
+                }
+                Some(allowed)
+            }
+            _ => return Err(DecodeError::InvalidValue),
+        };
+        let m = r.len()?;
+        for _ in 0..m {
+            let volume = r.f64()?;
+            let time = r.f64()?;
+            let source = r.container_id(n)?;
+            let target = r.container_id(n)?;
+            let delivery = r.delivery()?;
+            let jitter = r.f64()?;
+            let cooldown = r.f64()?;
+            let mut g = Grabber::new(source, target, volume, time)
+                .delivery(delivery)
+                .jitter(jitter)
+                .cooldown(cooldown);
+            g.condition = match r.u8()? {
+                0 => None,
+                1 => Some(Condition::Below(r.container_id(n)?, r.f64()?)),
+                2 => Some(Condition::Above(r.container_id(n)?, r.f64()?)),
+                _ => return Err(DecodeError::InvalidValue),
+            };
+            g.batch_size = r.option_f64()?;
+            g.require_space = r.bool()?;
+            g.auto = r.bool()?;
+            g.reaction_rate = r.f64()?;
+            g.wear_factor = r.f64()?;
+            g.min_volume = r.f64()?;
+            g.stages = r.u64()? as usize;
+            g.ramp = r.f64()?;
+            g.demand_threshold = match r.u8()? {
+                0 => None,
+                1 => Some((r.container_id(n)?, r.f64()?)),
+                _ => return Err(DecodeError::InvalidValue),
+            };
+            let gid = env.add_grabber(g);
+            env.grabber_states[gid.0] = GrabberState {
+                time: r.f64()?,
+                volume: r.f64()?,
+                moved: r.f64()?,
+                cooldown: r.f64()?,
+                duration: r.f64()?,
+                activations: r.u64()?,
+                streak: r.u64()?,
+                idle_since: r.f64()?,
+                frozen: r.bool()?,
+            };
+        }
+        env.allowed_transfers = allowed_transfers;
+        let k = r.len()?;
+        for _ in 0..k {
+            let name = r.string()?;
+            let c = r.container_id(n)?;
+            env.alias_container(c, &name).map_err(|_| DecodeError::InvalidValue)?;
+        }
+        let k = r.len()?;
+        for _ in 0..k {
+            let len = r.len()?;
+            let mut group = Vec::with_capacity(len);
+            for _ in 0..len {group.push(r.grabber_id(m)?)}
+            env.link_grabbers(&group);
+        }
+        let k = r.len()?;
+        for _ in 0..k {
+            let len = r.len()?;
+            let mut group = Vec::with_capacity(len);
+            for _ in 0..len {group.push(r.grabber_id(m)?)}
+            env.share_capacity(&group);
+        }
+        env.saturation = r.option_f64()?;
+        env.gravity = r.f64()?;
+        env.delivery_cap = r.option_f64()?;
+        env.fallback = match r.u8()? {
+            0 => None,
+            1 => Some(r.container_id(n)?),
+            _ => return Err(DecodeError::InvalidValue),
+        };
+        env.rng.state = r.u64()?;
+        env.sensor_rng.state = r.u64()?;
+        env.total_leaked = r.f64()?;
+        env.total_work = r.f64()?;
+        env.exchange.added = r.f64()?;
+        env.exchange.removed = r.f64()?;
+        env.time = r.f64()?;
+        let k = r.len()?;
+        for _ in 0..k {
+            let at = r.f64()?;
+            let c = r.container_id(n)?;
+            env.inflows.push((at, c, r.f64()?));
+        }
+        if r.pos != data.len() {return Err(DecodeError::TrailingBytes)}
+        Ok(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.5).height(1.5).capacity(4.0).unit("L", 100.0));
+        let c = env.add_container(Container::new(0.0).sink());
+        let d = env.add_container(Container::new(3.25).leak_rate(0.125));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0).cooldown(0.5).require_space()
+            .stages(2));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 0.5)
+            .delivery(DeliveryMode::Continuous));
+        let da = env.add_grabber(Grabber::new(d, a, 0.75, 2.0).jitter(0.1).batch_size(0.25)
+            .condition(Condition::Below(a, 20.0)).demand_threshold(a, 12.0));
+        env.set_seed(3);
+        env.saturation = Some(100.0);
+        env.schedule_inflow(d, 1.5, 4.0);
+        env.link_grabbers(&[ab, bc]);
+        env.share_capacity(&[bc, da]);
+        env.set_fallback_container(d);
+        env.remove_container(c);
+        env.alias_container(b, "tank").unwrap();
+        assert!(env.grab(ab).is_ok());
+        assert!(env.is_busy(bc));
+        env.update(0.25);
+
+        let bytes = env.to_bytes();
+        let env2 = Environment::from_bytes(&bytes).unwrap();
+        assert_eq!(env2.to_bytes(), bytes);
+        assert_eq!(env2.containers.len(), 4);
+        assert_eq!(env2.grabbers.len(), 3);
+        for i in 0..4 {
+            assert_eq!(env2.volume_of_container(ContainerId(i)),
+                       env.volume_of_container(ContainerId(i)));
+        }
+        assert_eq!(env2.grabbers[1].delivery, DeliveryMode::Continuous);
+        assert_eq!(env2.grabbers[2].demand_threshold, Some((a, 12.0)));
+        assert!(env2.is_removed(c));
+        assert_eq!(env2.grabber_states[0].time, 0.75);
+        assert_eq!(env2.grabber_states[0].volume, 2.0);
+        assert_eq!(env2.now(), 0.25);
+        assert_eq!(env2.linked_grabbers(bc), vec![ab, bc]);
+        assert_eq!(env2.capacity_sharing_grabbers(da), vec![bc, da]);
+        assert_eq!(env2.container_by_name("tank"), Some(b));
+    }
+
+    #[test]
+    fn test_round_trip_allow_list() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.allow_transfer(b, a);
+        let bytes = env.to_bytes();
+        let env2 = Environment::from_bytes(&bytes).unwrap();
+        assert_eq!(env2.to_bytes(), bytes);
+        assert_eq!(env2.grabbers.len(), 1);
+        assert!(!env2.is_transfer_allowed(a, b));
+        assert!(env2.is_transfer_allowed(b, a));
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let mut bytes = Environment::new().to_bytes();
+        bytes[4] = 99;
+        assert_eq!(Environment::from_bytes(&bytes).err(),
+                   Some(DecodeError::UnsupportedVersion(99)));
+        assert_eq!(Environment::from_bytes(b"DIG").err(),
+                   Some(DecodeError::InvalidHeader));
+        let bytes = Environment::new().to_bytes();
+        assert_eq!(Environment::from_bytes(&bytes[..10]).err(),
+                   Some(DecodeError::UnexpectedEnd));
+    }
+}
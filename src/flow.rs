@@ -0,0 +1,73 @@
+//! Customizable semantics of taking material from and putting material into containers.
+
+use alloc::sync::Arc;
+
+use crate::{Container, Environment};
+
+/// Describes how material behaves when grabbers take it from or put it into containers.
+///
+/// This allows modeling e.g. stratified or non-mixing materials.
+/// Saturation and capacities are applied by the environment after putting material.
+pub trait FlowModel {
+    /// Takes up to some volume from a container, returning the volume taken.
+    fn take(&self, c: &mut Container, v: f64) -> f64;
+    /// Puts some volume into a container.
+    fn put(&self, c: &mut Container, v: f64);
+}
+
+/// The default flow model, where material is fully mixed
+/// and any part of the volume can be taken.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MixedFlow;
+
+impl FlowModel for MixedFlow {
+    fn take(&self, c: &mut Container, v: f64) -> f64 {c.take(v)}
+    fn put(&self, c: &mut Container, v: f64) {c.put(v)}
+}
+
+impl Environment {
+    /// Sets how grabbers take material from and put material into containers.
+    ///
+    /// The default is `MixedFlow`.
+    /// Copies of the environment share the flow model,
+    /// which takes `&self` and therefore can not hold state that differs between copies.
+    /// The flow model must be `Send + Sync`, so environments can be simulated on other threads.
+    /// The flow model is not encoded in the binary or scenario formats.
+    pub fn set_flow_model<M: FlowModel + Send + Sync + 'static>(&mut self, model: M) {
+        self.flow_model = Arc::new(model);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Leaves a residue in containers that grabbers can not take.
+    struct Residue(f64);
+
+    impl FlowModel for Residue {
+        fn take(&self, c: &mut Container, v: f64) -> f64 {
+            let v = v.min((c.volume - self.0).max(0.0));
+            c.volume -= v;
+            v
+        }
+        fn put(&self, c: &mut Container, v: f64) {c.put(v)}
+    }
+
+    #[test]
+    fn test_flow_model() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).capacity(8.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 20.0, 1.0));
+        env.set_flow_model(Residue(1.0));
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(a), 1.0);
+        assert_eq!(env.volume_of_container(b), 8.0);
+        env.containers[a.0].volume = 5.0;
+        let mut env2 = env.clone();
+        env2.grab(ab).unwrap();
+        assert_eq!(env2.volume_of_container(a), 1.0);
+    }
+}
@@ -0,0 +1,1234 @@
+//! Analysis of the network formed by containers and grabbers.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+
+use crate::{ContainerId, Environment, GrabberId, Origins, SolveError};
+
+impl Environment {
+    /// The transport rate of a grabber, as volume per time.
+    pub fn rate_of_grabber(&self, gid: GrabberId) -> f64 {
+        let g = &self.grabbers[gid.0];
+        g.volume / g.time
+    }
+
+    /// Finds the grabber with the lowest transport rate along a path.
+    ///
+    /// The slowest grabber limits the flow through the whole path.
+    /// Returns the first one when several grabbers have the same rate.
+    /// Returns `None` if the path is empty.
+    pub fn bottleneck(&self, path: &[GrabberId]) -> Option<GrabberId> {
+        let mut min: Option<(GrabberId, f64)> = None;
+        for &gid in path {
+            let rate = self.rate_of_grabber(gid);
+            match min {
+                Some((_, r)) if r <= rate => {}
+                _ => min = Some((gid, rate)),
+            }
+        }
+        min.map(|(gid, _)| gid)
+    }
+
+    /// Lists the containers that no grabber moves material from.
+    ///
+    /// These are the outputs of the network.
+    pub fn terminal_containers(&self) -> Vec<ContainerId> {
+        (0..self.containers.len()).map(ContainerId)
+            .filter(|&c| self.grabbers.iter().all(|g| g.source != c))
+            .collect()
+    }
+
+    /// Lists the containers that no grabber moves material into.
+    ///
+    /// These are the inputs of the network.
+    pub fn root_containers(&self) -> Vec<ContainerId> {
+        (0..self.containers.len()).map(ContainerId)
+            .filter(|&c| self.grabbers.iter().all(|g| g.target != c))
+            .collect()
+    }
+
+    /// Finds the path of grabbers with the lowest total transport time
+    /// between two containers, using Dijkstra's algorithm.
+    ///
+    /// Returns the grabbers along the path and the total time.
+    /// Returns `None` if the target can not be reached.
+    pub fn fastest_path(
+        &self,
+        from: ContainerId,
+        to: ContainerId
+    ) -> Option<(Vec<GrabberId>, f64)> {
+        let n = self.containers.len();
+        let mut dist = vec![f64::INFINITY; n];
+        let mut prev: Vec<Option<GrabberId>> = vec![None; n];
+        let mut done = vec![false; n];
+        dist[from.0] = 0.0;
+        loop {
+            let mut next: Option<usize> = None;
+            for i in 0..n {
+                if !done[i] && dist[i] < f64::INFINITY &&
+                   next.map(|j| dist[i] < dist[j]).unwrap_or(true) {
+                    next = Some(i);
+                }
+            }
+            let i = next?;
+            if i == to.0 {break}
+            done[i] = true;
+            for (j, g) in self.grabbers.iter().enumerate() {
+                if g.source.0 == i && dist[i] + g.time < dist[g.target.0] {
+                    dist[g.target.0] = dist[i] + g.time;
+                    prev[g.target.0] = Some(GrabberId(j));
+                }
+            }
+        }
+        let mut path = vec![];
+        let mut c = to;
+        while let Some(gid) = prev[c.0] {
+            if c == from {break}
+            path.push(gid);
+            c = self.grabbers[gid.0].source;
+        }
+        path.reverse();
+        Some((path, dist[to.0]))
+    }
+
+    /// Lists every path of grabbers between two containers
+    /// that does not visit a container twice.
+    ///
+    /// Paths are found by depth-first search, following grabbers in the order they were added.
+    /// Since the number of paths can grow exponentially with the size of the network,
+    /// the search stops after `max_paths` paths, if given.
+    /// The path from a container to itself is empty.
+    pub fn all_paths(
+        &self,
+        from: ContainerId,
+        to: ContainerId,
+        max_paths: Option<usize>
+    ) -> Vec<Vec<GrabberId>> {
+        fn search(
+            env: &Environment,
+            c: ContainerId,
+            to: ContainerId,
+            max_paths: usize,
+            visited: &mut [bool],
+            path: &mut Vec<GrabberId>,
+            paths: &mut Vec<Vec<GrabberId>>,
+        ) {
+            if paths.len() >= max_paths {return}
+            if c == to {
+                paths.push(path.clone());
+                return;
+            }
+            visited[c.0] = true;
+            for (i, g) in env.grabbers.iter().enumerate() {
+                if g.source != c || visited[g.target.0] {continue}
+                path.push(GrabberId(i));
+                search(env, g.target, to, max_paths, visited, path, paths);
+                path.pop();
+            }
+            visited[c.0] = false;
+        }
+
+        let mut paths = vec![];
+        let mut visited = vec![false; self.containers.len()];
+        search(self, from, to, max_paths.unwrap_or(usize::MAX),
+               &mut visited, &mut vec![], &mut paths);
+        paths
+    }
+
+    /// Measures how evenly the current flow is spread across the parallel paths
+    /// between two containers.
+    ///
+    /// The current rate of a grabber is the volume of its current transport
+    /// divided by the duration, or zero when it is not transporting.
+    /// The throughput of each path is the lowest current rate along it,
+    /// and paths are listed like in `all_paths`, up to 1000 paths.
+    /// The score is based on the effective number of paths, `(sum x)^2 / sum x^2`,
+    /// scaled so that an even split over all paths scores `1`
+    /// and funneling everything through one path scores `0`.
+    /// Returns `0` when there are fewer than two paths or no path has throughput.
+    pub fn path_balance(&self, from: ContainerId, to: ContainerId) -> f64 {
+        const MAX_PATHS: usize = 1000;
+
+        let current_rate = |gid: GrabberId| {
+            let s = &self.grabber_states[gid.0];
+            if s.time > 0.0 && s.duration > 0.0 {s.moved / s.duration} else {0.0}
+        };
+        let throughputs: Vec<f64> = self.all_paths(from, to, Some(MAX_PATHS)).iter()
+            .filter(|path| !path.is_empty())
+            .map(|path| path.iter().map(|&gid| current_rate(gid)).fold(f64::INFINITY, f64::min))
+            .collect();
+        let k = throughputs.len();
+        let sum: f64 = throughputs.iter().sum();
+        let sum_squares: f64 = throughputs.iter().map(|x| x * x).sum();
+        if k < 2 || sum_squares <= 0.0 {return 0.0}
+        let effective = sum * sum / sum_squares;
+        ((effective - 1.0) / (k - 1) as f64).clamp(0.0, 1.0)
+    }
+
+    /// Marks the containers from which material can reach a container,
+    /// including the container itself.
+    pub(crate) fn upstream_of(&self, c: ContainerId) -> Vec<bool> {
+        let mut visited = vec![false; self.containers.len()];
+        visited[c.0] = true;
+        let mut stack = vec![c];
+        while let Some(c) = stack.pop() {
+            for g in &self.grabbers {
+                if g.target == c && !visited[g.source.0] {
+                    visited[g.source.0] = true;
+                    stack.push(g.source);
+                }
+            }
+        }
+        visited
+    }
+
+    /// Marks the containers that material can reach from a container,
+    /// including the container itself.
+    #[cfg(feature = "std")]
+    pub(crate) fn downstream_mask(&self, c: ContainerId) -> Vec<bool> {
+        self.downstream_mask_excluding(c, None)
+    }
+
+    /// Marks the containers that material can reach from a container,
+    /// optionally without using one of the grabbers.
+    fn downstream_mask_excluding(&self, c: ContainerId, excluded: Option<GrabberId>) -> Vec<bool> {
+        let mut visited = vec![false; self.containers.len()];
+        visited[c.0] = true;
+        let mut stack = vec![c];
+        while let Some(c) = stack.pop() {
+            for (i, g) in self.grabbers.iter().enumerate() {
+                if excluded == Some(GrabberId(i)) {continue}
+                if g.source == c && !visited[g.target.0] {
+                    visited[g.target.0] = true;
+                    stack.push(g.target);
+                }
+            }
+        }
+        visited
+    }
+
+    /// Finds all containers that material can eventually reach from a container,
+    /// including the container itself.
+    #[cfg(feature = "std")]
+    pub fn reachable_from(&self, c: ContainerId) -> HashSet<ContainerId> {
+        self.downstream_mask(c).into_iter().enumerate()
+            .filter(|&(_, reached)| reached)
+            .map(|(i, _)| ContainerId(i))
+            .collect()
+    }
+
+    /// Finds all containers that material moved by a grabber can eventually reach,
+    /// including its target.
+    #[cfg(feature = "std")]
+    pub fn downstream_of(&self, gid: GrabberId) -> HashSet<ContainerId> {
+        self.reachable_from(self.grabbers[gid.0].target)
+    }
+
+    /// Returns whether some amount of material can ever be in a container,
+    /// regardless of timing.
+    ///
+    /// This counts all material upstream of the container,
+    /// including material in flight towards upstream containers.
+    /// Material is reduced by the consumed fraction of each grabber it passes through,
+    /// see `Grabber::reaction_rate`, along the path losing the least.
+    /// A non-empty fixed container upstream is an infinite supply,
+    /// while the volume of a fixed target never changes.
+    /// Material can not pass through other sink or fixed containers,
+    /// since they discard what is delivered into them.
+    pub fn is_achievable(&self, target: ContainerId, amount: f64) -> bool {
+        if self.containers[target.0].fixed {return amount <= self.containers[target.0].volume}
+        let keep = self.retention_to(target);
+        let mut total = 0.0;
+        for (i, c) in self.containers.iter().enumerate() {
+            // Material already in a container that discards deliveries can still leave it.
+            let stored = if keep[i] > 0.0 || i == target.0 {keep[i]} else {
+                self.grabbers.iter().filter(|g| g.source.0 == i)
+                    .map(|g| (1.0 - g.reaction_rate) * keep[g.target.0])
+                    .fold(0.0, f64::max)
+            };
+            if stored <= 0.0 {continue}
+            if c.fixed && c.volume > 0.0 {return true}
+            total += c.volume * stored + self.pending[i] * keep[i];
+        }
+        for (g, s) in self.grabbers.iter().zip(self.grabber_states.iter()) {
+            total += s.volume * (1.0 - g.reaction_rate) * keep[g.target.0];
+        }
+        amount <= total
+    }
+
+    /// The largest fraction of material delivered into each container that can reach a container,
+    /// after the consumed fractions of the grabbers along the way.
+    ///
+    /// The fraction is zero for containers that can not reach the container,
+    /// and for sink and fixed containers other than the container,
+    /// since they discard delivered material.
+    fn retention_to(&self, target: ContainerId) -> Vec<f64> {
+        let n = self.containers.len();
+        let mut keep = vec![0.0; n];
+        keep[target.0] = 1.0;
+        let blocked = |c: ContainerId| c != target &&
+            (self.containers[c.0].sink || self.containers[c.0].fixed);
+        // A path without cycles has fewer than `n` grabbers.
+        for _ in 1..n {
+            let mut changed = false;
+            for g in &self.grabbers {
+                if g.source == target || blocked(g.source) {continue}
+                let k = (1.0 - g.reaction_rate) * keep[g.target.0];
+                if k > keep[g.source.0] {
+                    keep[g.source.0] = k;
+                    changed = true;
+                }
+            }
+            if !changed {break}
+        }
+        keep
+    }
+
+    /// Returns whether removing a grabber would disconnect some container
+    /// from a container that material can reach it from.
+    ///
+    /// This is the case when there is no other path from the source to the target.
+    pub fn is_critical(&self, gid: GrabberId) -> bool {
+        let g = &self.grabbers[gid.0];
+        !self.downstream_mask_excluding(g.source, Some(gid))[g.target.0]
+    }
+
+    /// Estimates how much the steady-state throughput from root containers
+    /// and containers holding material to terminal containers
+    /// increases when a grabber moves more volume per transport.
+    ///
+    /// The throughput is the maximum flow through the network,
+    /// where each grabber can move material at its rate.
+    /// Counting containers holding material gives networks without roots a throughput,
+    /// e.g. a cycle feeding a terminal.
+    /// The gain is zero when the grabber is not limiting the throughput.
+    pub fn marginal_gain(&self, gid: GrabberId, extra_volume: f64) -> f64 {
+        let mut rates: Vec<f64> = (0..self.grabbers.len())
+            .map(|i| self.rate_of_grabber(GrabberId(i)))
+            .collect();
+        let roots = self.root_containers();
+        let terminals = self.terminal_containers();
+        // Containers without grabbers are not part of the network.
+        let sources: Vec<ContainerId> = (0..self.containers.len()).map(ContainerId)
+            .filter(|c| roots.contains(c) || self.containers[c.0].volume > 0.0)
+            .filter(|c| !terminals.contains(c)).collect();
+        let sinks: Vec<ContainerId> = terminals.iter().cloned()
+            .filter(|c| !roots.contains(c)).collect();
+        let (before, _) = self.max_flow_with(&sources, &sinks, &rates);
+        let g = &self.grabbers[gid.0];
+        rates[gid.0] = (g.volume + extra_volume) / g.time;
+        self.max_flow_with(&sources, &sinks, &rates).0 - before
+    }
+
+    /// Computes the maximum sustainable flow rate from a source container to a sink container.
+    ///
+    /// Each grabber can move material at its rate, see `rate_of_grabber`,
+    /// and the rates of parallel grabbers add up.
+    /// Returns infinity when the source is the sink.
+    pub fn max_flow(&self, source: ContainerId, sink: ContainerId) -> f64 {
+        if source == sink {return f64::INFINITY}
+        let rates: Vec<f64> = (0..self.grabbers.len())
+            .map(|i| self.rate_of_grabber(GrabberId(i)))
+            .collect();
+        self.max_flow_with(&[source], &[sink], &rates).0
+    }
+
+    /// Finds a smallest set of grabbers whose removal stops all flow into a container
+    /// from the other non-empty containers.
+    ///
+    /// This is the minimum cut of the network where every grabber counts as one,
+    /// the cheapest way to isolate the container.
+    pub fn min_cut_to(&self, sink: ContainerId) -> Vec<GrabberId> {
+        let sources: Vec<ContainerId> = (0..self.containers.len()).map(ContainerId)
+            .filter(|&c| c != sink && self.containers[c.0].volume > 0.0).collect();
+        let rates = vec![1.0; self.grabbers.len()];
+        let (_, reachable) = self.max_flow_with(&sources, &[sink], &rates);
+        (0..self.grabbers.len()).map(GrabberId)
+            .filter(|gid| {
+                let g = &self.grabbers[gid.0];
+                reachable[g.source.0] && !reachable[g.target.0]
+            })
+            .collect()
+    }
+
+    /// Computes the maximum flow from some containers to other containers,
+    /// with a rate for each grabber as its capacity, using the Edmonds-Karp algorithm.
+    ///
+    /// The containers flown from and to must be disjoint.
+    /// Also returns which containers are on the source side of a minimum cut.
+    fn max_flow_with(
+        &self,
+        sources: &[ContainerId],
+        sinks: &[ContainerId],
+        rates: &[f64]
+    ) -> (f64, Vec<bool>) {
+        const EPSILON: f64 = 1e-12;
+
+        let n = self.containers.len();
+        let (source, sink) = (n, n + 1);
+        let m = n + 2;
+        let mut residual = vec![0.0; m * m];
+        for (g, &rate) in self.grabbers.iter().zip(rates.iter()) {
+            if g.source != g.target {residual[g.source.0 * m + g.target.0] += rate}
+        }
+        for &c in sources {residual[source * m + c.0] = f64::INFINITY}
+        for &c in sinks {residual[c.0 * m + sink] = f64::INFINITY}
+
+        let mut flow = 0.0;
+        loop {
+            let mut prev: Vec<Option<usize>> = vec![None; m];
+            let mut queue = vec![source];
+            let mut k = 0;
+            while k < queue.len() && prev[sink].is_none() {
+                let u = queue[k];
+                k += 1;
+                for v in 0..m {
+                    if v != source && prev[v].is_none() && residual[u * m + v] > EPSILON {
+                        prev[v] = Some(u);
+                        queue.push(v);
+                    }
+                }
+            }
+            if prev[sink].is_none() {
+                let reachable = (0..n).map(|c| prev[c].is_some() || sources.contains(&ContainerId(c)));
+                return (flow, reachable.collect());
+            }
+            let mut bottleneck = f64::INFINITY;
+            let mut v = sink;
+            while let Some(u) = prev[v] {
+                bottleneck = bottleneck.min(residual[u * m + v]);
+                v = u;
+            }
+            let mut v = sink;
+            while let Some(u) = prev[v] {
+                residual[u * m + v] -= bottleneck;
+                residual[v * m + u] += bottleneck;
+                v = u;
+            }
+            flow += bottleneck;
+        }
+    }
+
+    /// The straight-line distance a grabber moves material,
+    /// from the position and height of the source to those of the target.
+    pub fn transport_distance(&self, gid: GrabberId) -> f64 {
+        let g = &self.grabbers[gid.0];
+        let (a, b) = (&self.containers[g.source.0], &self.containers[g.target.0]);
+        let dx = b.position[0] - a.position[0];
+        let dy = b.position[1] - a.position[1];
+        let dh = b.height - a.height;
+        crate::sqrt(dx * dx + dy * dy + dh * dh)
+    }
+
+    /// The average transport distance of the grabbers, weighted by their transport rates.
+    ///
+    /// This is how far material typically travels per transport.
+    /// Returns zero when no grabber moves any material.
+    pub fn mean_transport_distance(&self) -> f64 {
+        let (mut weighted, mut total) = (0.0, 0.0);
+        for i in 0..self.grabbers.len() {
+            let rate = self.rate_of_grabber(GrabberId(i));
+            weighted += rate * self.transport_distance(GrabberId(i));
+            total += rate;
+        }
+        if total > 0.0 {weighted / total} else {0.0}
+    }
+
+    /// Estimates how long material stays in a container on average,
+    /// as the volume divided by the total outflow rate.
+    ///
+    /// The outflow rate is the sum of the rates of grabbers drawing from the container
+    /// plus the leak rate of the container.
+    /// Returns `None` when nothing flows out of the container.
+    pub fn mean_residence_time(&self, c: ContainerId) -> Option<f64> {
+        let outflow = (0..self.grabbers.len())
+            .filter(|&i| self.grabbers[i].source == c)
+            .map(|i| self.rate_of_grabber(GrabberId(i)))
+            .sum::<f64>() + self.containers[c.0].leak_rate;
+        if outflow <= 0.0 {return None}
+        Some(self.containers[c.0].volume / outflow)
+    }
+
+    /// Computes the long-run fraction of material in each container,
+    /// treating the flow as a continuous-time Markov chain.
+    ///
+    /// Each unit of material is assumed to move from a container to another
+    /// with the total rate of the grabbers between them, independently of volumes,
+    /// capacities, conditions and timing.
+    /// Sink containers are absorbing, like containers without outgoing grabbers,
+    /// so the fraction in them is the material that eventually ends up there.
+    ///
+    /// Starts from the current distribution of volumes in containers,
+    /// or a uniform distribution if the containers are empty,
+    /// which matters when material can end up in different absorbing containers.
+    /// The fractions sum to one, unless there are no containers.
+    pub fn stationary_distribution(&self) -> Vec<f64> {
+        let rates: Vec<f64> = (0..self.grabbers.len())
+            .map(|i| self.rate_of_grabber(GrabberId(i)))
+            .collect();
+        self.stationary_distribution_with(&rates)
+    }
+
+    /// Computes the stationary distribution with given grabber rates,
+    /// see `stationary_distribution`.
+    ///
+    /// Containers that can return to every container they reach form closed classes,
+    /// which keep their material, while all other containers eventually empty.
+    /// The material reaching each class is computed from the expected time
+    /// spent in the other containers, and is spread within the class
+    /// by the stationary distribution of the class.
+    /// Both are found by solving linear equations, which takes cubic time in the containers.
+    fn stationary_distribution_with(&self, grabber_rates: &[f64]) -> Vec<f64> {
+        let n = self.containers.len();
+        if n == 0 {return vec![]}
+        let mut rates = vec![0.0; n * n];
+        for (i, g) in self.grabbers.iter().enumerate() {
+            if g.source == g.target || self.containers[g.source.0].sink {continue}
+            rates[g.source.0 * n + g.target.0] += grabber_rates[i];
+        }
+        let exit: Vec<f64> = (0..n).map(|i| rates[i * n..(i + 1) * n].iter().sum()).collect();
+        let total: f64 = self.containers.iter().map(|c| c.volume).sum();
+        let mut p: Vec<f64> = if total > 0.0 {
+            self.containers.iter().map(|c| c.volume / total).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        // `reach[i * n + j]` is whether material can move from `i` to `j`.
+        let mut reach = vec![false; n * n];
+        for i in 0..n {
+            let mut stack = vec![i];
+            reach[i * n + i] = true;
+            while let Some(j) = stack.pop() {
+                for k in 0..n {
+                    if rates[j * n + k] > 0.0 && !reach[i * n + k] {
+                        reach[i * n + k] = true;
+                        stack.push(k);
+                    }
+                }
+            }
+        }
+        let closed: Vec<bool> = (0..n)
+            .map(|i| (0..n).all(|j| !reach[i * n + j] || reach[j * n + i]))
+            .collect();
+
+        // Move the material of transient containers into closed classes.
+        // The expected time `t` spent in transient containers solves `t (-Q) = p`,
+        // where `Q` is the rate matrix restricted to transient containers.
+        let transient: Vec<usize> = (0..n).filter(|&i| !closed[i]).collect();
+        let m = transient.len();
+        if m > 0 {
+            let mut a = vec![0.0; m * m];
+            for (row, &j) in transient.iter().enumerate() {
+                for (col, &i) in transient.iter().enumerate() {
+                    a[row * m + col] = if i == j {exit[i]} else {-rates[i * n + j]};
+                }
+            }
+            let b: Vec<f64> = transient.iter().map(|&i| p[i]).collect();
+            let t = solve_linear(a, b);
+            for (&i, &t) in transient.iter().zip(t.iter()) {
+                for j in 0..n {
+                    if closed[j] {p[j] += t * rates[i * n + j]}
+                }
+                p[i] = 0.0;
+            }
+        }
+
+        // Spread the material of each closed class by solving `pi Q = 0` with `sum(pi) = 1`.
+        let mut done = vec![false; n];
+        for i in 0..n {
+            if !closed[i] || done[i] {continue}
+            let class: Vec<usize> = (0..n).filter(|&j| closed[j] && reach[i * n + j]).collect();
+            for &j in &class {done[j] = true}
+            let k = class.len();
+            if k == 1 {continue}
+            let mass: f64 = class.iter().map(|&j| p[j]).sum();
+            let mut a = vec![0.0; k * k];
+            let mut b = vec![0.0; k];
+            for (row, &j) in class.iter().enumerate() {
+                for (col, &i) in class.iter().enumerate() {
+                    a[row * k + col] = if row == k - 1 {1.0}
+                        else if i == j {-exit[i]}
+                        else {rates[i * n + j]};
+                }
+            }
+            b[k - 1] = 1.0;
+            for (&j, pi) in class.iter().zip(solve_linear(a, b)) {
+                p[j] = mass * pi;
+            }
+        }
+        p
+    }
+
+    /// Searches for grabber times that make the steady state reach target container volumes.
+    ///
+    /// The steady state is the stationary distribution of the current total volume,
+    /// see `stationary_distribution`, as if grabbers were activated continuously.
+    /// Starting from the current times, each time is scaled up and down in turn
+    /// while that brings the steady state closer to the target,
+    /// with smaller steps when no change helps.
+    /// Scaling all times by the same factor gives the same steady state,
+    /// so the result is one of many timings, close to the current ones.
+    ///
+    /// Each evaluation of the steady state solves linear equations, see `stationary_distribution`.
+    ///
+    /// Returns the times of the grabbers, in order, once every volume is within the tolerance.
+    /// The environment is not changed.
+    pub fn solve_timings(&self, target: &[f64], tolerance: f64) -> Result<Vec<f64>, SolveError> {
+        const MIN_STEP: f64 = 1e-9;
+        const MAX_ITERATIONS: usize = 10_000;
+
+        if target.len() != self.containers.len() {return Err(SolveError::LengthMismatch)}
+        if !(tolerance.is_finite() && tolerance >= 0.0) {return Err(SolveError::InvalidTolerance)}
+        let total: f64 = self.containers.iter().map(|c| c.volume).sum();
+        let residual = |times: &[f64]| -> f64 {
+            let rates: Vec<f64> = self.grabbers.iter().zip(times.iter())
+                .map(|(g, &t)| g.volume / t)
+                .collect();
+            self.stationary_distribution_with(&rates).iter().zip(target.iter())
+                .map(|(p, v)| (p * total - v).abs())
+                .fold(0.0, f64::max)
+        };
+
+        let mut times: Vec<f64> = self.grabbers.iter()
+            .map(|g| if g.time > 0.0 && g.time.is_finite() {g.time} else {1.0})
+            .collect();
+        let mut best = residual(&times);
+        let mut step = 1.0;
+        for _ in 0..MAX_ITERATIONS {
+            if best <= tolerance {return Ok(times)}
+            let mut improved = false;
+            for i in 0..times.len() {
+                for factor in [1.0 + step, 1.0 / (1.0 + step)] {
+                    let time = times[i];
+                    times[i] = time * factor;
+                    let r = residual(&times);
+                    if r < best {
+                        best = r;
+                        improved = true;
+                    } else {
+                        times[i] = time;
+                    }
+                }
+            }
+            if !improved {
+                step *= 0.5;
+                if step < MIN_STEP {break}
+            }
+        }
+        if best <= tolerance {Ok(times)} else {Err(SolveError::Infeasible {residual: best})}
+    }
+
+    /// Estimates how much of the material that will reach a goal container
+    /// originates from each container.
+    ///
+    /// Material is routed like in `stationary_distribution`:
+    /// it leaves a container through its grabbers in proportion to their rates,
+    /// and stays in sinks and containers without outgoing grabbers.
+    /// Material in flight is attributed to the source of its grabber,
+    /// like provenance tracking does, and material waiting to be delivered to its target.
+    /// Material already in the goal is attributed to the goal.
+    /// Only containers with a positive contribution are included.
+    pub fn source_contributions(&self, goal: ContainerId) -> Origins {
+        const EPSILON: f64 = 1e-12;
+        const MAX_ITERATIONS: usize = 100_000;
+
+        let n = self.containers.len();
+        let mut exit = vec![0.0; n];
+        for (i, g) in self.grabbers.iter().enumerate() {
+            if g.source != g.target && !self.containers[g.source.0].sink {
+                exit[g.source.0] += self.rate_of_grabber(GrabberId(i));
+            }
+        }
+        // The fraction of material in each container that eventually reaches the goal.
+        let upstream = self.upstream_of(goal);
+        let mut reach = vec![0.0; n];
+        reach[goal.0] = 1.0;
+        for _ in 0..MAX_ITERATIONS {
+            let mut change: f64 = 0.0;
+            for i in (0..n).filter(|&i| upstream[i] && i != goal.0 && exit[i] > 0.0) {
+                let r = self.grabbers.iter().enumerate()
+                    .filter(|(_, g)| g.source.0 == i && g.target.0 != i)
+                    .map(|(j, g)| self.rate_of_grabber(GrabberId(j)) * reach[g.target.0])
+                    .sum::<f64>() / exit[i];
+                change = change.max((r - reach[i]).abs());
+                reach[i] = r;
+            }
+            if change < EPSILON {break}
+        }
+
+        let mut contributions = vec![0.0; n];
+        for i in 0..n {
+            contributions[i] += (self.containers[i].volume + self.pending[i]) * reach[i];
+        }
+        for (g, s) in self.grabbers.iter().zip(self.grabber_states.iter()) {
+            contributions[g.source.0] += s.volume * reach[g.target.0];
+        }
+        let mut origins = Origins::new();
+        for (i, &v) in contributions.iter().enumerate() {
+            if v > 0.0 {origins.insert(ContainerId(i), v);}
+        }
+        origins
+    }
+
+    /// Finds the grabbers that can never move any material.
+    ///
+    /// A grabber is dead when no material can ever reach its source:
+    /// the source and every container upstream of it are empty,
+    /// with nothing in flight, waiting to be delivered or scheduled to flow in.
+    pub fn dead_grabbers(&self) -> Vec<GrabberId> {
+        let mut fed = vec![false; self.containers.len()];
+        for (i, c) in self.containers.iter().enumerate() {
+            if c.volume > 0.0 || self.pending[i] > 0.0 {fed[i] = true}
+        }
+        for (g, s) in self.grabbers.iter().zip(self.grabber_states.iter()) {
+            if s.volume > 0.0 {fed[g.target.0] = true}
+        }
+        for &(_, c, amount) in &self.inflows {
+            if amount > 0.0 {fed[c.0] = true}
+        }
+        let mut stack: Vec<ContainerId> = (0..fed.len())
+            .filter(|&i| fed[i]).map(ContainerId).collect();
+        while let Some(c) = stack.pop() {
+            for g in &self.grabbers {
+                if g.source == c && !fed[g.target.0] {
+                    fed[g.target.0] = true;
+                    stack.push(g.target);
+                }
+            }
+        }
+        (0..self.grabbers.len())
+            .filter(|&i| !fed[self.grabbers[i].source.0])
+            .map(GrabberId)
+            .collect()
+    }
+
+    /// Exports the network in the DOT format of Graphviz.
+    ///
+    /// Containers are labeled with their volume and grabbers with their volume and time,
+    /// both shown in the unit of the container, see `Container::unit`.
+    pub fn to_dot(&self) -> String {
+        let mut s = String::from("digraph {\n");
+        for (i, c) in self.containers.iter().enumerate() {
+            let mut label = String::new();
+            write!(label, "{}", c).unwrap();
+            writeln!(s, "    c{} [label=\"{}\"];", i, escape_label(&label)).unwrap();
+        }
+        for g in &self.grabbers {
+            let c = &self.containers[g.source.0];
+            let mut label = String::new();
+            write!(label, "{}", g.volume * c.unit_scale).unwrap();
+            if let Some(unit) = &c.unit {write!(label, " {}", unit).unwrap()}
+            write!(label, " / {}", g.time).unwrap();
+            writeln!(s, "    c{} -> c{} [label=\"{}\"];",
+                     g.source.0, g.target.0, escape_label(&label)).unwrap();
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+/// Escapes quotes and backslashes for use in a quoted DOT label.
+fn escape_label(label: &str) -> String {
+    let mut s = String::with_capacity(label.len());
+    for ch in label.chars() {
+        if ch == '"' || ch == '\\' {s.push('\\')}
+        s.push(ch);
+    }
+    s
+}
+
+/// Solves the linear equations `a x = b` by Gaussian elimination with partial pivoting,
+/// where `a` is a square matrix stored by rows.
+///
+/// Unknowns without a pivot are set to zero.
+fn solve_linear(mut a: Vec<f64>, mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i * n + col].abs().total_cmp(&a[j * n + col].abs()))
+            .unwrap();
+        if a[pivot * n + col] == 0.0 {continue}
+        for k in 0..n {a.swap(col * n + k, pivot * n + k)}
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let f = a[row * n + col] / a[col * n + col];
+            if f == 0.0 {continue}
+            for k in col..n {a[row * n + k] -= f * a[col * n + k]}
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let d = a[row * n + row];
+        if d == 0.0 {continue}
+        let sum: f64 = (row + 1..n).map(|k| a[row * n + k] * x[k]).sum();
+        x[row] = (b[row] - sum) / d;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_bottleneck() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 2.0, 4.0));
+        assert_eq!(env.bottleneck(&[ab, bc]), Some(bc));
+        assert_eq!(env.bottleneck(&[ab]), Some(ab));
+        assert_eq!(env.bottleneck(&[]), None);
+
+        let bc2 = env.add_grabber(Grabber::new(b, c, 1.0, 2.0));
+        assert_eq!(env.bottleneck(&[bc, bc2]), Some(bc));
+    }
+
+    #[test]
+    fn test_terminal_and_root_containers() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.root_containers(), vec![a]);
+        assert_eq!(env.terminal_containers(), vec![c]);
+        let d = env.add_container(Container::new(0.0));
+        assert_eq!(env.root_containers(), vec![a, d]);
+        assert_eq!(env.terminal_containers(), vec![c, d]);
+    }
+
+    #[test]
+    fn test_fastest_path() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let d = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 2.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, d, 1.0, 2.0));
+        let cd = env.add_grabber(Grabber::new(c, d, 1.0, 1.5));
+        assert_eq!(env.fastest_path(a, d), Some((vec![ac, cd], 2.5)));
+        assert_eq!(env.fastest_path(a, a), Some((vec![], 0.0)));
+        assert_eq!(env.fastest_path(d, a), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_reachable_from() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let d = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.add_grabber(Grabber::new(d, a, 1.0, 1.0));
+        assert_eq!(env.reachable_from(a), [a, b, c].iter().cloned().collect());
+        assert_eq!(env.reachable_from(c), [c].iter().cloned().collect());
+        assert_eq!(env.reachable_from(d).len(), 4);
+    }
+
+    #[test]
+    fn test_path_balance() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(5.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.grab(ab).unwrap();
+        assert_eq!(env.path_balance(a, b), 0.0);
+        let ac = env.add_grabber(Grabber::new(a, c, 2.0, 1.0));
+        let cb = env.add_grabber(Grabber::new(c, b, 1.0, 1.0));
+        // Idle paths carry no flow.
+        assert_eq!(env.path_balance(a, b), 0.0);
+        env.grab(ac).unwrap();
+        env.grab(cb).unwrap();
+        assert_eq!(env.path_balance(a, b), 1.0);
+
+        env.update(1.0);
+        env.grabbers[ab.0].volume = 0.01;
+        env.grab(ab).unwrap();
+        env.grab(ac).unwrap();
+        env.grab(cb).unwrap();
+        let lopsided = env.path_balance(a, b);
+        assert!(lopsided > 0.0 && lopsided < 0.1);
+        env.update(1.0);
+        env.grab(ab).unwrap();
+        env.grab(cb).unwrap();
+        assert_eq!(env.path_balance(a, b), 0.0);
+    }
+
+    #[test]
+    fn test_all_paths() {
+        let mut env = Environment::new();
+        let s = env.add_container(Container::new(10.0));
+        let a = env.add_container(Container::new(0.0));
+        let b = env.add_container(Container::new(0.0));
+        let t = env.add_container(Container::new(0.0));
+        let sa = env.add_grabber(Grabber::new(s, a, 1.0, 1.0));
+        let sb = env.add_grabber(Grabber::new(s, b, 1.0, 1.0));
+        let at = env.add_grabber(Grabber::new(a, t, 1.0, 1.0));
+        let bt = env.add_grabber(Grabber::new(b, t, 1.0, 1.0));
+        let ts = env.add_grabber(Grabber::new(t, s, 1.0, 1.0));
+        assert_eq!(env.all_paths(s, t, None), vec![vec![sa, at], vec![sb, bt]]);
+        assert_eq!(env.all_paths(s, t, Some(1)), vec![vec![sa, at]]);
+        assert_eq!(env.all_paths(s, s, None), vec![Vec::<GrabberId>::new()]);
+        assert_eq!(env.all_paths(s, s, Some(0)), Vec::<Vec<GrabberId>>::new());
+        assert_eq!(env.all_paths(s, t, Some(0)), Vec::<Vec<GrabberId>>::new());
+        assert_eq!(env.all_paths(t, a, None), vec![vec![ts, sa]]);
+    }
+
+    #[test]
+    fn test_mean_transport_distance() {
+        let mut env = Environment::new();
+        assert_eq!(env.mean_transport_distance(), 0.0);
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).position(3.0, 4.0));
+        let c = env.add_container(Container::new(0.0).position(3.0, 4.0).height(12.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 3.0, 3.0));
+        assert_eq!(env.transport_distance(ab), 5.0);
+        assert_eq!(env.transport_distance(bc), 12.0);
+        assert_eq!(env.transport_distance(ac), 13.0);
+        assert_eq!(env.mean_transport_distance(), (2.0 * 5.0 + 12.0 + 13.0) / 4.0);
+    }
+
+    #[test]
+    fn test_solve_timings() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(9.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        let times = env.solve_timings(&[3.0, 6.0], 1e-6).unwrap();
+        assert!((times[ba.0] / times[ab.0] - 2.0).abs() < 1e-4);
+
+        let mut solved = env.clone();
+        solved.grabbers[ab.0].time = times[ab.0];
+        solved.grabbers[ba.0].time = times[ba.0];
+        let p = solved.stationary_distribution();
+        assert!((p[a.0] * 9.0 - 3.0).abs() < 1e-6);
+        assert_eq!(env.grabbers[ab.0].time, 1.0);
+
+        assert_eq!(env.solve_timings(&[3.0], 1e-6), Err(SolveError::LengthMismatch));
+        assert_eq!(env.solve_timings(&[3.0, 6.0], -1.0), Err(SolveError::InvalidTolerance));
+        assert_eq!(env.solve_timings(&[3.0, 6.0], f64::NAN), Err(SolveError::InvalidTolerance));
+        // Material is conserved, so a different total can not be reached.
+        assert!(matches!(env.solve_timings(&[3.0, 3.0], 1e-6),
+                         Err(SolveError::Infeasible {..})));
+    }
+
+    #[test]
+    fn test_source_contributions() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let contributions = env.source_contributions(c);
+        assert_eq!(contributions.len(), 1);
+        assert_eq!(contributions[&a], 10.0);
+
+        // Material at `b` is split evenly between `c` and `d`.
+        let d = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(b, d, 2.0, 2.0));
+        env.containers[b.0].volume = 4.0;
+        env.grab(bc).unwrap();
+        let contributions = env.source_contributions(c);
+        assert_eq!(contributions[&a], 5.0);
+        assert_eq!(contributions[&b], 2.5);
+        assert!(!contributions.contains_key(&c));
+        assert_eq!(env.source_contributions(a)[&a], 10.0);
+    }
+
+    #[test]
+    fn test_is_achievable() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.5));
+        let c = env.add_container(Container::new(0.0));
+        let d = env.add_container(Container::new(5.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.add_grabber(Grabber::new(c, d, 1.0, 1.0));
+        assert!(env.is_achievable(c, 1.5));
+        assert!(!env.is_achievable(c, 2.0));
+        assert!(env.grab(ab).is_ok());
+        assert!(env.is_achievable(c, 1.5));
+        assert!(!env.is_achievable(a, 0.5));
+        assert!(env.is_achievable(d, 6.5));
+    }
+
+    #[test]
+    fn test_is_achievable_losses() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(4.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0).reaction_rate(0.5));
+        env.add_grabber(Grabber::new(b, c, 4.0, 1.0).reaction_rate(0.5));
+        assert!(env.is_achievable(c, 1.0));
+        assert!(!env.is_achievable(c, 1.5));
+        assert!(env.is_achievable(b, 2.0));
+
+        // A lossless bypass is preferred.
+        let ac = env.add_grabber(Grabber::new(a, c, 4.0, 1.0));
+        assert!(env.is_achievable(c, 4.0));
+        env.grabbers[ac.0].reaction_rate = 1.0;
+        env.grab(ab).unwrap();
+        assert!(env.is_achievable(c, 1.0));
+        assert!(!env.is_achievable(c, 1.5));
+    }
+
+    #[test]
+    fn test_is_achievable_fixed() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0).fixed());
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0).fixed());
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert!(env.is_achievable(b, 100.0));
+        assert!(!env.is_achievable(c, 1.0));
+        assert!(env.is_achievable(c, 0.0));
+    }
+
+    #[test]
+    fn test_is_achievable_through_sink() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(5.0));
+        let b = env.add_container(Container::new(0.0).sink());
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert!(!env.is_achievable(c, 1.0));
+        env.grab(ab).unwrap();
+        assert!(!env.is_achievable(c, 1.0));
+        // Material already in the sink can still leave it.
+        env.containers[b.0].volume = 2.0;
+        assert!(env.is_achievable(c, 2.0));
+        assert!(!env.is_achievable(c, 3.0));
+    }
+
+    #[test]
+    fn test_dead_grabbers() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let d = env.add_container(Container::new(0.0));
+        let e = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let dc = env.add_grabber(Grabber::new(d, c, 1.0, 1.0));
+        env.add_grabber(Grabber::new(e, c, 1.0, 1.0));
+        env.schedule_inflow(e, 1.0, 5.0);
+        assert_eq!(env.dead_grabbers(), vec![dc]);
+    }
+
+    #[test]
+    fn test_mean_residence_time() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(4.0));
+        env.add_grabber(Grabber::new(a, b, 2.0, 0.5));
+        assert_eq!(env.mean_residence_time(a), Some(2.5));
+        assert_eq!(env.mean_residence_time(b), None);
+    }
+
+    #[test]
+    fn test_is_critical() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert!(env.is_critical(ab));
+        assert!(env.is_critical(bc));
+
+        let ab2 = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        assert!(!env.is_critical(ab));
+        assert!(!env.is_critical(ab2));
+        assert!(env.is_critical(bc));
+    }
+
+    #[test]
+    fn test_stationary_distribution() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        let p = env.stationary_distribution();
+        assert!((p.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!((p[0] - 1.0 / 3.0).abs() < 1e-9);
+        assert!((p[1] - 2.0 / 3.0).abs() < 1e-9);
+
+        let c = env.add_container(Container::new(0.0).sink());
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let p = env.stationary_distribution();
+        assert!((p[2] - 1.0).abs() < 1e-9);
+        assert_eq!(Environment::new().stationary_distribution(), vec![]);
+    }
+
+    #[test]
+    fn test_stationary_distribution_slow_mixing() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let d = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1e-6, 1.0));
+        env.add_grabber(Grabber::new(b, a, 2e-6, 1.0));
+        env.add_grabber(Grabber::new(c, d, 1.0, 1.0));
+        env.add_grabber(Grabber::new(d, c, 1.0, 1.0));
+        let p = env.stationary_distribution();
+        assert!((p[a.0] - 2.0 / 3.0).abs() < 1e-9);
+        assert!((p[b.0] - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(p[c.0] + p[d.0], 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_downstream_of() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.downstream_of(ab), [b, c].iter().cloned().collect());
+        assert_eq!(env.downstream_of(bc), [c].iter().cloned().collect());
+        env.add_grabber(Grabber::new(c, a, 1.0, 1.0));
+        assert_eq!(env.downstream_of(bc), [a, b, c].iter().cloned().collect());
+    }
+
+    #[test]
+    fn test_marginal_gain() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.marginal_gain(bc, 2.0), 2.0);
+        assert_eq!(env.marginal_gain(bc, 5.0), 3.0);
+        assert_eq!(env.marginal_gain(ab, 2.0), 0.0);
+    }
+
+    #[test]
+    fn test_marginal_gain_cycle() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0));
+        env.add_grabber(Grabber::new(b, a, 4.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.marginal_gain(bc, 2.0), 2.0);
+        assert_eq!(env.marginal_gain(ab, 2.0), 0.0);
+    }
+
+    #[test]
+    fn test_max_flow() {
+        let mut env = Environment::new();
+        let s = env.add_container(Container::new(10.0));
+        let a = env.add_container(Container::new(0.0));
+        let b = env.add_container(Container::new(0.0));
+        let t = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(s, a, 3.0, 1.0));
+        env.add_grabber(Grabber::new(s, b, 2.0, 1.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(a, t, 1.0, 1.0));
+        env.add_grabber(Grabber::new(a, t, 0.5, 0.5));
+        env.add_grabber(Grabber::new(b, t, 4.0, 1.0));
+        // The minimum cut is `s -> a` and `s -> b`.
+        assert_eq!(env.max_flow(s, t), 5.0);
+        assert_eq!(env.max_flow(a, t), 3.0);
+        assert_eq!(env.max_flow(t, s), 0.0);
+    }
+
+    #[test]
+    fn test_min_cut_to() {
+        let mut env = Environment::new();
+        let s = env.add_container(Container::new(10.0));
+        let a = env.add_container(Container::new(0.0));
+        let b = env.add_container(Container::new(0.0));
+        let t = env.add_container(Container::new(0.0));
+        let sa = env.add_grabber(Grabber::new(s, a, 1.0, 1.0));
+        let sb = env.add_grabber(Grabber::new(s, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(a, t, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, t, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, t, 1.0, 1.0));
+        assert_eq!(env.min_cut_to(t), vec![sa, sb]);
+        assert_eq!(env.min_cut_to(a), vec![sa]);
+        assert!(env.min_cut_to(s).is_empty());
+        assert!(env.max_flow(s, t) > 0.0);
+        for gid in env.min_cut_to(t) {
+            env.grabbers[gid.0].volume = 0.0;
+        }
+        assert_eq!(env.max_flow(s, t), 0.0);
+    }
+
+    #[test]
+    fn test_min_cut_to_cycle() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(5.0));
+        let b = env.add_container(Container::new(0.0));
+        let t = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        env.add_grabber(Grabber::new(t, a, 1.0, 1.0));
+        let bt = env.add_grabber(Grabber::new(b, t, 1.0, 1.0));
+        assert!(env.root_containers().is_empty());
+        assert_eq!(env.min_cut_to(t), vec![bt]);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(8.0).unit("L", 100.0));
+        let b = env.add_container(Container::new(0.5));
+        env.add_grabber(Grabber::new(a, b, 2.0, 1.5));
+        env.add_grabber(Grabber::new(b, a, 0.25, 1.0));
+        assert_eq!(env.to_dot(), concat!(
+            "digraph {\n",
+            "    c0 [label=\"800 L\"];\n",
+            "    c1 [label=\"0.5\"];\n",
+            "    c0 -> c1 [label=\"200 L / 1.5\"];\n",
+            "    c1 -> c0 [label=\"0.25 / 1\"];\n",
+            "}\n",
+        ));
+    }
+
+    #[test]
+    fn test_to_dot_escape() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0).unit("\"in\\", 1.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        assert_eq!(env.to_dot(), concat!(
+            "digraph {\n",
+            "    c0 [label=\"1 \\\"in\\\\\"];\n",
+            "    c1 [label=\"0\"];\n",
+            "    c0 -> c1 [label=\"1 \\\"in\\\\ / 1\"];\n",
+            "}\n",
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+//! Opt-in recording of container volumes over time.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{ContainerId, Environment};
+
+/// Stores the volumes of tracked containers over time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    /// The tracked containers.
+    pub containers: Vec<ContainerId>,
+    /// Samples of the time and the volume of each tracked container.
+    pub samples: Vec<(f64, Vec<f64>)>,
+}
+
+impl History {
+    /// Returns the volume of the `k`-th tracked container at a time,
+    /// interpolating linearly between samples.
+    ///
+    /// Returns `None` when the time is outside the recorded range.
+    fn interpolate(&self, k: usize, t: f64) -> Option<f64> {
+        let (first, last) = (self.samples.first()?.0, self.samples.last()?.0);
+        if !(first..=last).contains(&t) {return None}
+        let i = self.samples.partition_point(|&(time, _)| time < t);
+        let (t1, v1) = (self.samples[i].0, self.samples[i].1[k]);
+        if i == 0 || t1 == t {return Some(v1)}
+        let (t0, v0) = (self.samples[i - 1].0, self.samples[i - 1].1[k]);
+        Some(v0 + (v1 - v0) * (t - t0) / (t1 - t0))
+    }
+}
+
+impl Environment {
+    /// Starts recording the volumes of containers after every update.
+    ///
+    /// The current volumes are recorded as the first sample.
+    /// Replaces any previously recorded history.
+    pub fn track_history(&mut self, containers: &[ContainerId]) {
+        self.history = Some(History {containers: containers.into(), samples: vec![]});
+        self.record_history();
+    }
+
+    /// Returns the recorded history, if any.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Records a sample of the tracked containers, if history is recorded.
+    pub(crate) fn record_history(&mut self) {
+        if let Some(h) = &mut self.history {
+            let containers = &self.containers;
+            let volumes = h.containers.iter().map(|c| containers[c.0].volume).collect();
+            h.samples.push((self.time, volumes));
+        }
+    }
+
+    /// Returns the volume of a container at a time within the recorded history,
+    /// interpolating linearly between samples.
+    ///
+    /// Returns `None` when the container is not tracked or the time is outside the recorded range.
+    pub fn volume_at(&self, c: ContainerId, t: f64) -> Option<f64> {
+        let h = self.history.as_ref()?;
+        let k = h.containers.iter().position(|&tracked| tracked == c)?;
+        h.interpolate(k, t)
+    }
+
+    /// Computes the L2 distance between two recorded histories,
+    /// integrated over the time range both histories cover.
+    ///
+    /// Volumes are interpolated linearly between samples,
+    /// so the histories do not need to be sampled at the same times.
+    /// Only containers tracked by both histories are compared.
+    /// Returns zero when the time ranges do not overlap.
+    pub fn trajectory_distance(history_a: &History, history_b: &History) -> f64 {
+        let pairs: Vec<(usize, usize)> = history_a.containers.iter().enumerate()
+            .filter_map(|(ka, c)| history_b.containers.iter().position(|cb| cb == c)
+                .map(|kb| (ka, kb)))
+            .collect();
+        let (start, end) = match (history_a.samples.first(), history_a.samples.last(),
+                                  history_b.samples.first(), history_b.samples.last()) {
+            (Some(a0), Some(a1), Some(b0), Some(b1)) => (a0.0.max(b0.0), a1.0.min(b1.0)),
+            _ => return 0.0,
+        };
+        if start >= end {return 0.0}
+
+        let mut times: Vec<f64> = history_a.samples.iter().chain(history_b.samples.iter())
+            .map(|&(t, _)| t)
+            .filter(|&t| t > start && t < end)
+            .collect();
+        times.push(start);
+        times.push(end);
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times.dedup();
+
+        let diff = |t: f64| -> Vec<f64> {
+            pairs.iter().map(|&(ka, kb)| {
+                history_a.interpolate(ka, t).unwrap() - history_b.interpolate(kb, t).unwrap()
+            }).collect()
+        };
+        // The difference is linear between sample times, so its square is integrated exactly.
+        let mut integral = 0.0;
+        let mut d0 = diff(times[0]);
+        for w in times.windows(2) {
+            let d1 = diff(w[1]);
+            let sum: f64 = d0.iter().zip(d1.iter()).map(|(a, b)| a * a + a * b + b * b).sum();
+            integral += (w[1] - w[0]) * sum / 3.0;
+            d0 = d1;
+        }
+        crate::sqrt(integral)
+    }
+
+    /// Returns whether the volume of a container oscillates
+    /// over the most recent samples of the recorded history.
+    ///
+    /// The volume oscillates when its change switches sign at least twice
+    /// without dying out, i.e. the largest change in the second half of the window
+    /// is at least half the largest change in the first half.
+    /// Returns `false` when the container is not tracked or there are too few samples.
+    pub fn is_oscillating(&self, c: ContainerId, history_window: usize) -> bool {
+        const EPSILON: f64 = 1e-9;
+
+        let h = match &self.history {
+            Some(h) => h,
+            None => return false,
+        };
+        let k = match h.containers.iter().position(|&t| t == c) {
+            Some(k) => k,
+            None => return false,
+        };
+        let start = h.samples.len().saturating_sub(history_window);
+        let volumes: Vec<f64> = h.samples[start..].iter().map(|(_, v)| v[k]).collect();
+        let deltas: Vec<f64> = volumes.windows(2).map(|w| w[1] - w[0])
+            .filter(|d| d.abs() > EPSILON).collect();
+        let sign_changes = deltas.windows(2).filter(|w| (w[0] > 0.0) != (w[1] > 0.0)).count();
+        if sign_changes < 2 {return false}
+        let half = deltas.len() / 2;
+        let max_abs = |ds: &[f64]| ds.iter().map(|d| d.abs()).fold(0.0, f64::max);
+        max_abs(&deltas[half..]) >= 0.5 * max_abs(&deltas[..half])
+    }
+
+    /// Formats the recorded history as CSV.
+    ///
+    /// The header is `time` followed by `container.i` for each tracked container `i`,
+    /// with one row per sample.
+    /// Returns an empty string when history is not recorded.
+    pub fn history_to_csv(&self) -> String {
+        let h = match &self.history {
+            Some(h) => h,
+            None => return String::new(),
+        };
+        let mut csv = String::from("time");
+        for c in &h.containers {
+            csv.push_str(&format!(",container.{}", c.0));
+        }
+        csv.push('\n');
+        for (time, volumes) in &h.samples {
+            csv.push_str(&format!("{}", time));
+            for v in volumes {
+                csv.push_str(&format!(",{}", v));
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_history_to_csv() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 2.0, 1.0));
+        assert_eq!(env.history_to_csv(), "");
+        env.track_history(&[a, c]);
+        env.grab(ac).unwrap();
+        for _ in 0..3 {
+            env.update(0.5);
+        }
+        assert_eq!(env.history().unwrap().samples.len(), 4);
+        let csv = env.history_to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 5);
+        assert!(rows.iter().all(|row| row.split(',').count() == 3));
+        assert_eq!(rows[0], "time,container.0,container.2");
+        assert_eq!(rows[1], "0,10,0");
+        assert_eq!(rows[3], "1,8,2");
+    }
+
+    #[test]
+    fn test_is_oscillating() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(0.0));
+        let b = env.add_container(Container::new(0.0));
+        assert!(!env.is_oscillating(a, 10));
+        env.track_history(&[a]);
+        for k in 0..8 {
+            env.containers[a.0].volume = if k % 2 == 0 {4.0} else {1.0};
+            env.update(1.0);
+        }
+        assert!(env.is_oscillating(a, 8));
+        assert!(!env.is_oscillating(b, 8));
+
+        env.track_history(&[a]);
+        for k in 0..8 {
+            env.containers[a.0].volume = k as f64;
+            env.update(1.0);
+        }
+        assert!(!env.is_oscillating(a, 8));
+
+        env.track_history(&[a]);
+        let mut v = 4.0;
+        for _ in 0..8 {
+            v *= -0.25;
+            env.containers[a.0].volume = 5.0 + v;
+            env.update(1.0);
+        }
+        assert!(!env.is_oscillating(a, 8));
+    }
+
+    #[test]
+    fn test_trajectory_distance() {
+        fn run(delay: usize) -> History {
+            let mut env = Environment::new();
+            let a = env.add_container(Container::new(10.0));
+            let b = env.add_container(Container::new(0.0));
+            let ab = env.add_grabber(Grabber::new(a, b, 1.0, 0.5));
+            env.track_history(&[a, b]);
+            for k in 0..10 {
+                if k >= delay {let _ = env.grab(ab);}
+                env.update(0.5);
+            }
+            env.history().unwrap().clone()
+        }
+
+        let h = run(0);
+        assert_eq!(Environment::trajectory_distance(&h, &h), 0.0);
+        let shifted = run(2);
+        let d = Environment::trajectory_distance(&h, &shifted);
+        assert!(d > 0.0);
+        assert_eq!(Environment::trajectory_distance(&shifted, &h), d);
+
+        // Sampling at different times does not change a linear trajectory.
+        let mut coarse = h.clone();
+        coarse.samples = coarse.samples.into_iter().step_by(2).collect();
+        let mut line = h.clone();
+        for (t, v) in &mut line.samples {*v = vec![10.0 - *t, *t]}
+        for (t, v) in &mut coarse.samples {*v = vec![10.0 - *t, *t]}
+        assert!(Environment::trajectory_distance(&line, &coarse) < 1e-12);
+    }
+
+    #[test]
+    fn test_volume_at() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0));
+        assert_eq!(env.volume_at(a, 0.0), None);
+        env.track_history(&[a]);
+        env.update(1.0);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        env.update(1.0);
+        assert_eq!(env.volume_at(a, 0.5), Some(10.0));
+        assert_eq!(env.volume_at(a, 1.0), Some(10.0));
+        assert_eq!(env.volume_at(a, 1.5), Some(8.0));
+        assert_eq!(env.volume_at(a, 3.0), Some(6.0));
+        assert_eq!(env.volume_at(a, 3.5), None);
+        assert_eq!(env.volume_at(a, -0.5), None);
+        assert_eq!(env.volume_at(b, 1.5), None);
+    }
+}
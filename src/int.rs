@@ -0,0 +1,220 @@
+//! Environments with discrete integer volumes.
+//!
+//! Some materials consist of countable items that can not be split.
+//! An integer environment stores volumes as `u64`,
+//! so grabbers move whole units only and no rounding errors accumulate.
+//!
+//! This is a simpler variant of `Environment`:
+//! grabbers deliver all material at the end of the time interval,
+//! and there are no conditions, cooldowns or other extensions.
+
+use alloc::vec::Vec;
+
+use crate::{ContainerId, GrabError, GrabberId, Transition};
+
+/// Stores a whole number of units of some material.
+#[derive(Clone, Debug)]
+pub struct IntContainer {
+    /// The number of units in the container.
+    pub volume: u64,
+    /// Whether units delivered to the container are discarded.
+    pub sink: bool,
+}
+
+impl IntContainer {
+    /// Creates a new container with some units.
+    pub fn new(volume: u64) -> IntContainer {
+        IntContainer {volume, sink: false}
+    }
+
+    /// Makes the container a sink, which discards delivered units.
+    pub fn sink(mut self) -> IntContainer {
+        self.sink = true;
+        self
+    }
+
+    /// Adds some units to the container.
+    pub fn put(&mut self, v: u64) {
+        self.volume += v;
+    }
+
+    /// Takes up to some units from the container.
+    ///
+    /// Returns the number of units taken.
+    pub fn take(&mut self, v: u64) -> u64 {
+        let v = v.min(self.volume);
+        self.volume -= v;
+        v
+    }
+}
+
+/// Stores information about a grabber moving whole units.
+#[derive(Clone, Debug)]
+pub struct IntGrabber {
+    /// The maximum number of units moved at once.
+    pub volume: u64,
+    /// The time it takes to move the units.
+    pub time: f64,
+    /// The container to take units from.
+    pub source: ContainerId,
+    /// The container to put units in.
+    pub target: ContainerId,
+}
+
+impl IntGrabber {
+    /// Creates a new grabber.
+    pub fn new(source: ContainerId, target: ContainerId, volume: u64, time: f64) -> IntGrabber {
+        IntGrabber {volume, time, source, target}
+    }
+}
+
+/// Stores the state of a grabber moving whole units.
+#[derive(Clone, Debug, Default)]
+pub struct IntGrabberState {
+    /// The time remaining until the grabber is done.
+    pub time: f64,
+    /// The number of units moved by the grabber.
+    pub volume: u64,
+}
+
+/// Stores an Internal Environment with integer volumes.
+#[derive(Clone, Debug, Default)]
+pub struct IntEnvironment {
+    /// Stores containers.
+    pub containers: Vec<IntContainer>,
+    /// Stores grabbers.
+    pub grabbers: Vec<IntGrabber>,
+    /// Stores grabber states.
+    pub grabber_states: Vec<IntGrabberState>,
+    /// The number of units discarded by sinks.
+    total_sunk: u64,
+}
+
+impl IntEnvironment {
+    /// Creates a new empty environment.
+    pub fn new() -> IntEnvironment {
+        IntEnvironment::default()
+    }
+
+    /// Adds a container.
+    pub fn add_container(&mut self, c: IntContainer) -> ContainerId {
+        let id = self.containers.len();
+        self.containers.push(c);
+        ContainerId(id)
+    }
+
+    /// Adds a grabber.
+    pub fn add_grabber(&mut self, g: IntGrabber) -> GrabberId {
+        let id = self.grabbers.len();
+        self.grabbers.push(g);
+        self.grabber_states.push(IntGrabberState::default());
+        GrabberId(id)
+    }
+
+    /// Returns `true` if the grabber is transporting units.
+    pub fn is_busy(&self, gid: GrabberId) -> bool {
+        self.grabber_states[gid.0].time > 0.0
+    }
+
+    /// Activates a grabber, taking up to its volume of units from the source.
+    pub fn grab(&mut self, gid: GrabberId) -> Result<(), GrabError> {
+        if self.is_busy(gid) {return Err(GrabError::Busy)}
+        let g = &self.grabbers[gid.0];
+        let v = self.containers[g.source.0].take(g.volume);
+        let s = &mut self.grabber_states[gid.0];
+        s.volume = v;
+        s.time = g.time;
+        if s.time <= 0.0 {
+            // Instant transports are delivered right away.
+            self.grabber_states[gid.0].volume = 0;
+            self.put_into(self.grabbers[gid.0].target, v);
+        }
+        Ok(())
+    }
+
+    /// Updates the environment with a time delta.
+    ///
+    /// Returns the grabbers that were busy before the update
+    /// together with how they changed, see `Environment::update`.
+    pub fn update(&mut self, dt: f64) -> Vec<(GrabberId, Transition)> {
+        let mut transitions = Vec::new();
+        for i in 0..self.grabbers.len() {
+            if !self.is_busy(GrabberId(i)) {continue}
+            let s = &mut self.grabber_states[i];
+            s.time -= dt;
+            if s.time > 0.0 {
+                transitions.push((GrabberId(i), Transition::Continued));
+                continue
+            }
+            s.time = 0.0;
+            let v = s.volume;
+            s.volume = 0;
+            self.put_into(self.grabbers[i].target, v);
+            transitions.push((GrabberId(i), Transition::Finished));
+        }
+        transitions
+    }
+
+    fn put_into(&mut self, c: ContainerId, v: u64) {
+        if self.containers[c.0].sink {
+            self.total_sunk += v;
+        } else {
+            self.containers[c.0].put(v);
+        }
+    }
+
+    /// Returns the number of units in a container.
+    pub fn volume_of_container(&self, c: ContainerId) -> u64 {
+        self.containers[c.0].volume
+    }
+
+    /// Returns the number of units being transported by grabbers.
+    pub fn in_flight_volume(&self) -> u64 {
+        self.grabber_states.iter().map(|s| s.volume).sum()
+    }
+
+    /// Returns the total number of units in containers and in flight.
+    ///
+    /// Together with `total_sunk`, this is conserved exactly.
+    pub fn total_volume(&self) -> u64 {
+        self.containers.iter().map(|c| c.volume).sum::<u64>() + self.in_flight_volume()
+    }
+
+    /// Returns the number of units discarded by sinks.
+    pub fn total_sunk(&self) -> u64 {
+        self.total_sunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_chain() {
+        let mut env = IntEnvironment::new();
+        let a = env.add_container(IntContainer::new(10));
+        let b = env.add_container(IntContainer::new(0));
+        let c = env.add_container(IntContainer::new(0).sink());
+        let ab = env.add_grabber(IntGrabber::new(a, b, 3, 1.0));
+        let bc = env.add_grabber(IntGrabber::new(b, c, 2, 0.5));
+
+        env.grab(ab).unwrap();
+        assert_eq!(env.grab(ab), Err(GrabError::Busy));
+        assert_eq!(env.volume_of_container(a), 7);
+        assert_eq!(env.update(0.5), vec![(ab, Transition::Continued)]);
+        assert_eq!(env.update(0.5), vec![(ab, Transition::Finished)]);
+        assert_eq!(env.volume_of_container(b), 3);
+
+        for _ in 0..4 {
+            let _ = env.grab(ab);
+            let _ = env.grab(bc);
+            env.update(1.0);
+        }
+        assert_eq!(env.volume_of_container(a), 0);
+        assert_eq!(env.volume_of_container(b), 2);
+        assert_eq!(env.total_sunk(), 8);
+        assert_eq!(env.total_volume() + env.total_sunk(), 10);
+    }
+}
@@ -1,4 +1,5 @@
 #![deny(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 //! # Dig
 //! A simple logistic environment primitive
@@ -31,6 +32,8 @@
 //! then all the remaining material in the container is moved by the grabber.
 //! At the end of the time interval,
 //! the resource is put in the target container.
+//! Alternatively, a grabber can deliver the resource continuously
+//! over the time interval.
 //!
 //! ### Internal vs External Environment
 //!
@@ -41,30 +44,217 @@
 //!
 //! The distinction between internal and external is used to formalize the
 //! language used to talk about safety in environments.
+//!
+//! ### Features
+//!
+//! - `std` (default): Enables functionality that requires the standard library.
+//!   Without it, the crate only depends on `core` and `alloc`.
+//! - `rayon`: Enables updating grabber timers on multiple threads, using `rayon`.
+//! - `debug-invariants`: Checks internal invariants after every grab and update.
+
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+pub use batch::Batch;
+pub use binary::{DecodeError, FORMAT_VERSION};
+pub use flow::{FlowModel, MixedFlow};
+pub use history::History;
+pub use int::{IntContainer, IntEnvironment, IntGrabber, IntGrabberState};
+pub use log::{Event, EventKind};
+pub use validate::ValidationError;
+pub use scenario::{ScenarioError, SCENARIO_VERSION};
+pub use policy::{
+    GreedyPolicy,
+    Policy,
+    RoundRobinPolicy,
+    ThresholdPolicy,
+    TickReport,
+};
+
+mod batch;
+mod binary;
+mod flow;
+mod graph;
+mod history;
+mod int;
+mod log;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod policy;
+mod random;
+mod rng;
+mod scenario;
+mod validate;
+
+use rng::Rng;
 
 /// Stores volume of some material.
-pub struct Container(pub f64);
+#[derive(Clone, Debug)]
+pub struct Container {
+    /// The volume of material in the container.
+    pub volume: f64,
+    /// Whether material delivered to the container is discarded.
+    pub sink: bool,
+    /// Whether the volume of the container never changes.
+    pub fixed: bool,
+    /// The height of the container, used to compute transport work.
+    pub height: f64,
+    /// The volume lost per time unit.
+    pub leak_rate: f64,
+    /// The maximum volume the container can hold.
+    pub capacity: f64,
+    /// The unit label used when displaying the volume, if any.
+    pub unit: Option<String>,
+    /// The factor converting the volume to the displayed unit.
+    pub unit_scale: f64,
+    /// The horizontal position of the container, used to compute transport distances.
+    pub position: [f64; 2],
+}
 
 impl Container {
+    /// Creates a new container with some volume.
+    pub fn new(volume: f64) -> Container {
+        Container {
+            volume,
+            sink: false,
+            fixed: false,
+            height: 0.0,
+            leak_rate: 0.0,
+            capacity: f64::INFINITY,
+            unit: None,
+            unit_scale: 1.0,
+            position: [0.0, 0.0],
+        }
+    }
+
+    /// Makes the container a sink, which discards delivered material.
+    pub fn sink(mut self) -> Container {
+        self.sink = true;
+        self
+    }
+
+    /// Makes the container fixed, keeping its volume constant like a boundary condition.
+    ///
+    /// Material can be taken from a fixed container without decreasing its volume,
+    /// and material put into it is discarded.
+    /// A fixed container does not leak or decay.
+    pub fn fixed(mut self) -> Container {
+        self.fixed = true;
+        self
+    }
+
+    /// Sets the height of the container.
+    pub fn height(mut self, height: f64) -> Container {
+        self.height = height;
+        self
+    }
+
+    /// Sets the horizontal position of the container.
+    ///
+    /// Together with the height, this places the container in space.
+    pub fn position(mut self, x: f64, y: f64) -> Container {
+        self.position = [x, y];
+        self
+    }
+
+    /// Sets the volume lost per time unit.
+    pub fn leak_rate(mut self, leak_rate: f64) -> Container {
+        self.leak_rate = leak_rate;
+        self
+    }
+
+    /// Sets the unit used when displaying the volume.
+    ///
+    /// The volume is multiplied by the scale for display and `Environment::to_dot`,
+    /// while the simulation stays in internal units.
+    pub fn unit(mut self, unit: &str, scale: f64) -> Container {
+        self.unit = Some(unit.into());
+        self.unit_scale = scale;
+        self
+    }
+
+    /// Sets the maximum volume the container can hold.
+    ///
+    /// Material delivered beyond the capacity spills and is lost.
+    pub fn capacity(mut self, capacity: f64) -> Container {
+        self.capacity = capacity;
+        self
+    }
+
     /// Adds some volume to the container.
+    ///
+    /// Does nothing if the container is fixed.
     pub fn put(&mut self, v: f64) {
-        self.0 += v;
+        if !self.fixed {self.volume += v}
+    }
+
+    /// Adds some volume to the container, saturating at a maximum volume.
+    ///
+    /// The volume never becomes infinite or NaN.
+    /// Does nothing if the container is fixed.
+    pub fn put_saturating(&mut self, v: f64, max: f64) {
+        if self.fixed {return}
+        let x = self.volume + v;
+        if !x.is_nan() {
+            self.volume = x.min(max);
+        }
     }
 
     /// Takes some volume from the container.
+    ///
+    /// A fixed container supplies up to its volume without decreasing.
     pub fn take(&mut self, v: f64) -> f64 {
-        if self.0 <= v {
-            let v = self.0;
-            self.0 = 0.0;
+        if self.fixed {
+            v.min(self.volume)
+        } else if self.volume <= v {
+            let v = self.volume;
+            self.volume = 0.0;
             v
         } else {
-            self.0 -= v;
+            self.volume -= v;
             v
         }
     }
 }
 
+impl From<f64> for Container {
+    /// Creates a new container with some volume, see `Container::new`.
+    fn from(volume: f64) -> Container {
+        Container::new(volume)
+    }
+}
+
+impl fmt::Display for Container {
+    /// Displays the volume in the unit of the container, e.g. `800 L`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let volume = self.volume * self.unit_scale;
+        match &self.unit {
+            Some(unit) => write!(f, "{} {}", volume, unit),
+            None => write!(f, "{}", volume),
+        }
+    }
+}
+
 /// Stores information about a grabber.
+#[derive(Clone, Debug)]
 pub struct Grabber {
     /// The maximum volume capacity of the grabber.
     pub volume: f64,
@@ -74,14 +264,270 @@ pub struct Grabber {
     pub source: ContainerId,
     /// Stores target container ID.
     pub target: ContainerId,
+    /// How moved material is delivered to the target.
+    pub delivery: DeliveryMode,
+    /// The maximum random deviation of the transport time per activation.
+    pub jitter: f64,
+    /// The time the grabber rests after a transport before it can be activated again.
+    pub cooldown: f64,
+    /// The condition that must hold for the grabber to be activated.
+    pub condition: Option<Condition>,
+    /// The size of discrete batches the grabber moves material in, if any.
+    pub batch_size: Option<f64>,
+    /// Whether the target must have room for the moved volume on activation.
+    pub require_space: bool,
+    /// Whether the grabber activates by itself whenever it can.
+    pub auto: bool,
+    /// The fraction of delivered volume consumed at the target.
+    pub reaction_rate: f64,
+    /// The factor the volume of the grabber is multiplied with per activation.
+    pub wear_factor: f64,
+    /// The volume the grabber does not wear below.
+    pub min_volume: f64,
+    /// The number of stages material passes through during a lump transport.
+    pub stages: usize,
+    /// The fraction of the volume gained per consecutive activation,
+    /// where `1` means the grabber moves its full volume at once.
+    pub ramp: f64,
+    /// The container and level below which an auto grabber reactivates, if any.
+    pub demand_threshold: Option<(ContainerId, f64)>,
+}
+
+impl Grabber {
+    /// Creates a new grabber moving from source to target.
+    pub fn new(source: ContainerId, target: ContainerId, volume: f64, time: f64) -> Grabber {
+        Grabber {
+            volume,
+            time,
+            source,
+            target,
+            delivery: DeliveryMode::Lump,
+            jitter: 0.0,
+            cooldown: 0.0,
+            condition: None,
+            batch_size: None,
+            require_space: false,
+            auto: false,
+            reaction_rate: 0.0,
+            wear_factor: 1.0,
+            min_volume: 0.0,
+            stages: 1,
+            ramp: 1.0,
+            demand_threshold: None,
+        }
+    }
+
+    /// Sets how moved material is delivered to the target.
+    pub fn delivery(mut self, delivery: DeliveryMode) -> Grabber {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Sets the maximum random deviation of the transport time.
+    ///
+    /// Each activation draws a time uniformly within `time ± jitter`,
+    /// using the seeded random number generator of the environment.
+    pub fn jitter(mut self, jitter: f64) -> Grabber {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the time the grabber rests after completing a transport.
+    ///
+    /// No material is moved during cooldown, but the grabber is still busy.
+    pub fn cooldown(mut self, cooldown: f64) -> Grabber {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Sets the condition that must hold for the grabber to be activated.
+    pub fn condition(mut self, condition: Condition) -> Grabber {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Sets the size of discrete batches the grabber moves material in.
+    ///
+    /// The grabber only moves whole batches,
+    /// leaving any remainder in the source.
+    pub fn batch_size(mut self, batch_size: f64) -> Grabber {
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    /// Requires the target to have room for the moved volume on activation.
+    ///
+    /// Material in flight towards the target is counted as taking up room.
+    pub fn require_space(mut self) -> Grabber {
+        self.require_space = true;
+        self
+    }
+
+    /// Makes the grabber activate by itself during updates
+    /// whenever it is idle and its source is not empty.
+    pub fn auto(mut self) -> Grabber {
+        self.auto = true;
+        self
+    }
+
+    /// Sets the fraction of delivered volume consumed at the target.
+    ///
+    /// The target gains `delivered * (1 - reaction_rate)`, the rest vanishes.
+    pub fn reaction_rate(mut self, reaction_rate: f64) -> Grabber {
+        self.reaction_rate = reaction_rate;
+        self
+    }
+
+    /// Makes the volume of the grabber wear with use.
+    ///
+    /// After each activation, the volume is multiplied by the wear factor,
+    /// but it does not wear below the minimum volume.
+    pub fn wear(mut self, wear_factor: f64, min_volume: f64) -> Grabber {
+        self.wear_factor = wear_factor;
+        self.min_volume = min_volume;
+        self
+    }
+
+    /// Makes the grabber transport material as a pipeline of stages.
+    ///
+    /// The moved volume is split into equal portions,
+    /// one of which is delivered at the end of each stage of the transport time.
+    /// A single stage delivers all material at the end.
+    /// Ignored for continuous delivery.
+    pub fn stages(mut self, stages: usize) -> Grabber {
+        self.stages = stages;
+        self
+    }
+
+    /// Makes the grabber warm up over consecutive activations.
+    ///
+    /// The `k`-th consecutive activation moves at most `min(1, k * ramp)` of the volume.
+    /// Activations are consecutive when the grabber is activated again
+    /// as soon as it becomes idle, any idle time resets the ramp.
+    pub fn ramp(mut self, ramp: f64) -> Grabber {
+        self.ramp = ramp;
+        self
+    }
+
+    /// Makes an auto grabber reactivate only while a container is below a level.
+    ///
+    /// The container is usually the target, such that material is pulled by demand downstream
+    /// instead of being pushed whenever the source has material.
+    /// Manual activations are not affected.
+    pub fn demand_threshold(mut self, c: ContainerId, level: f64) -> Grabber {
+        self.demand_threshold = Some((c, level));
+        self
+    }
+}
+
+/// Describes a condition on the volume of a container.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    /// The volume of the container is below some value.
+    Below(ContainerId, f64),
+    /// The volume of the container is above some value.
+    Above(ContainerId, f64),
+}
+
+impl Condition {
+    /// Returns whether the condition holds in an environment.
+    pub fn holds(&self, env: &Environment) -> bool {
+        match *self {
+            Condition::Below(c, v) => env.volume_of_container(c) < v,
+            Condition::Above(c, v) => env.volume_of_container(c) > v,
+        }
+    }
+}
+
+/// Describes why a grabber could not be activated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrabError {
+    /// The grabber is busy.
+    Busy,
+    /// The condition of the grabber does not hold.
+    ConditionUnmet,
+    /// The target does not have room for the moved volume.
+    TargetFull,
+    /// Another grabber sharing capacity with the grabber is busy.
+    SharedBusy,
+}
+
+impl fmt::Display for GrabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrabError::Busy => write!(f, "Grabber is busy"),
+            GrabError::ConditionUnmet => write!(f, "Grabber condition is not met"),
+            GrabError::TargetFull => write!(f, "Grabber target is full"),
+            GrabError::SharedBusy => write!(f, "Grabber shares capacity with a busy grabber"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for GrabError {}
+
+/// Describes how a grabber delivers material to its target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// All material is put in the target at the end of the time interval.
+    Lump,
+    /// Material is put in the target linearly over the time interval.
+    Continuous,
 }
 
 /// Stores the grabber state.
+#[derive(Clone, Debug, Default)]
 pub struct GrabberState {
     /// The time remaining until the grabber is done.
     pub time: f64,
     /// The volume moved by the grabber.
     pub volume: f64,
+    /// The total volume moved by the current transport.
+    pub moved: f64,
+    /// The cooldown time remaining after a completed transport.
+    pub cooldown: f64,
+    /// The total time of the current transport.
+    pub duration: f64,
+    /// The number of times the grabber has been activated.
+    pub activations: u64,
+    /// The number of consecutive activations without idle time in between.
+    pub streak: u64,
+    /// The time the most recent transport and its cooldown ended.
+    pub idle_since: f64,
+    /// Whether the timer of the grabber is paused.
+    pub frozen: bool,
+}
+
+/// Describes how a grabber changed during an update.
+///
+/// A grabber is busy while transporting or cooling down, see `Environment::is_busy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// The grabber went from idle to busy.
+    ///
+    /// This happens when an auto grabber is activated during the update.
+    Started,
+    /// The grabber was busy before and after the update.
+    Continued,
+    /// The grabber went from busy to idle.
+    Finished,
+}
+
+/// Describes what a grabber is doing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrabberStatus {
+    /// The grabber can be activated.
+    Idle,
+    /// The grabber is transporting material.
+    Transporting {
+        /// The time remaining until the transport completes.
+        remaining: f64,
+    },
+    /// The grabber rests after a completed transport.
+    CoolingDown {
+        /// The time remaining until the grabber is idle.
+        remaining: f64,
+    },
 }
 
 /// Stores the Internal Environment.
@@ -92,15 +538,500 @@ pub struct Environment {
     pub grabbers: Vec<Grabber>,
     /// Stores grabber states.
     pub grabber_states: Vec<GrabberState>,
+    /// Stores provenance, if tracked.
+    provenance: Option<Provenance>,
+    /// Called when a grabber is activated.
+    on_grab: Vec<Hook>,
+    /// Called when a grabber completes a transport.
+    on_complete: Vec<Hook>,
+    /// Called when containers rise above their high watermarks.
+    watermarks: Vec<Watermark>,
+    /// Generates random numbers for stochastic behavior.
+    rng: Rng,
+    /// Generates sensor noise, separately such that readings do not affect the simulation.
+    sensor_rng: Rng,
+    /// Stores the container pairs that grabbers may connect, if restricted.
+    allowed_transfers: Option<Vec<(ContainerId, ContainerId)>>,
+    /// The volume at which deliveries into containers saturate, if any.
+    pub saturation: Option<f64>,
+    /// The volume discarded into each sink container.
+    sunk: Vec<f64>,
+    /// The gravitational acceleration used to compute transport work.
+    pub gravity: f64,
+    /// The total volume lost by leaking containers.
+    total_leaked: f64,
+    /// The total work done by completed transports.
+    total_work: f64,
+    /// The total volume exchanged with the outside of the environment.
+    exchange: EnvDiff,
+    /// The maximum volume a container can receive per update, if limited.
+    pub delivery_cap: Option<f64>,
+    /// The volume waiting to be delivered to each container.
+    pending: Vec<f64>,
+    /// Whether each container has been removed.
+    removed: Vec<bool>,
+    /// The maximum volume each container has reached.
+    peaks: Vec<f64>,
+    /// The container receiving deliveries to removed containers, if any.
+    fallback: Option<ContainerId>,
+    /// The simulated time.
+    time: f64,
+    /// Scheduled inflows as `(time, container, amount)`, sorted by time.
+    inflows: Vec<(f64, ContainerId, f64)>,
+    /// Disjoint groups of grabbers that activate together.
+    links: Vec<Vec<GrabberId>>,
+    /// Disjoint groups of grabbers of which at most one is busy at a time.
+    shared: Vec<Vec<GrabberId>>,
+    /// Names referring to containers.
+    names: Vec<(String, ContainerId)>,
+    /// Stores events, if logged.
+    log: Option<Vec<Event>>,
+    /// Stores container volumes over time, if recorded.
+    history: Option<History>,
+    /// Stores the most recent grab, if it can be undone.
+    undo: Option<Undo>,
+    /// How grabbers take and put material.
+    flow_model: Arc<dyn FlowModel + Send + Sync>,
+}
+
+/// Callback invoked with a grabber ID and the volume it moves.
+///
+/// Callbacks must be `Send`, so environments can be simulated on other threads.
+pub type Hook = Box<dyn FnMut(GrabberId, f64) + Send>;
+
+/// Callback invoked with a container ID and its volume.
+///
+/// Like `Hook`, it must be `Send`.
+pub type ContainerHook = Box<dyn FnMut(ContainerId, f64) + Send>;
+
+/// Stores a high watermark of a container.
+struct Watermark {
+    container: ContainerId,
+    level: f64,
+    /// Whether the volume was above the level when last checked.
+    above: bool,
+    callback: ContainerHook,
 }
 
 /// Stores a container ID.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ContainerId(pub usize);
 /// Stores a grabber ID.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GrabberId(pub usize);
 
+/// Error when adding a grabber between containers that may not be connected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisallowedTransfer {
+    /// The source container.
+    pub source: ContainerId,
+    /// The target container.
+    pub target: ContainerId,
+}
+
+impl fmt::Display for DisallowedTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Transfer from container {} to container {} is not allowed",
+               self.source.0, self.target.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for DisallowedTransfer {}
+
+/// Error when giving a container a name that is already taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTaken {
+    /// The name.
+    pub name: String,
+    /// The container the name refers to.
+    pub container: ContainerId,
+}
+
+impl fmt::Display for NameTaken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Name `{}` already refers to container {}", self.name, self.container.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for NameTaken {}
+
+/// Error when referring to containers that do not exist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownContainers {
+    /// The containers that do not exist, in the order they were referred to.
+    pub containers: Vec<ContainerId>,
+}
+
+impl fmt::Display for UnknownContainers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown containers")?;
+        for (i, c) in self.containers.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 {""} else {","}, c.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for UnknownContainers {}
+
+/// Error when undoing the most recent grab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UndoError {
+    /// There is no grab to undo.
+    NothingToUndo,
+    /// A grabber of the grab has already delivered material.
+    Delivered,
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UndoError::NothingToUndo => write!(f, "There is no grab to undo"),
+            UndoError::Delivered => write!(f, "Grabber has already delivered material"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for UndoError {}
+
+/// Error when solving for grabber timings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolveError {
+    /// The number of target volumes differs from the number of containers.
+    LengthMismatch,
+    /// The tolerance is negative or not finite.
+    InvalidTolerance,
+    /// No timings were found that reach the target within the tolerance.
+    Infeasible {
+        /// The largest difference from the target of the best timings found.
+        residual: f64,
+    },
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SolveError::LengthMismatch => write!(f, "Target does not match the number of containers"),
+            SolveError::InvalidTolerance => write!(f, "Tolerance must be finite and non-negative"),
+            SolveError::Infeasible {residual} =>
+                write!(f, "No timings reach the target, the best differ by {}", residual),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for SolveError {}
+
+/// A summary of the environment state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnvStats {
+    /// The total volume in containers, in flight and waiting to be delivered.
+    pub total_volume: f64,
+    /// The number of busy grabbers.
+    pub busy_grabbers: usize,
+    /// The number of empty containers.
+    pub empty_containers: usize,
+    /// The total volume in flight or waiting to be delivered.
+    pub in_flight_volume: f64,
+    /// The simulated time.
+    pub time: f64,
+}
+
+/// Stores volume exchanged with the outside of the environment.
+///
+/// Material is removed by leaking, sinks, saturation, spilling and reactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EnvDiff {
+    /// The volume added to the environment.
+    pub added: f64,
+    /// The volume removed from the environment.
+    pub removed: f64,
+}
+
+impl EnvDiff {
+    /// The volume exchanged since an earlier reading.
+    pub fn since(&self, earlier: &EnvDiff) -> EnvDiff {
+        EnvDiff {
+            added: self.added - earlier.added,
+            removed: self.removed - earlier.removed,
+        }
+    }
+}
+
+/// Records an activation, such that it can be reverted.
+#[derive(Clone)]
+struct GrabRecord {
+    /// The activated grabber.
+    gid: GrabberId,
+    /// The grabber state before activation.
+    state: GrabberState,
+    /// The volume taken from the source.
+    taken: f64,
+    /// The volume of the grabber before wear.
+    volume: f64,
+}
+
+/// Records the most recent grab, such that it can be undone.
+#[derive(Clone)]
+struct Undo {
+    /// The activations of the grab.
+    records: Vec<GrabRecord>,
+    /// The random number generator before the grab.
+    rng: Rng,
+    /// Whether any of the grabbers has delivered material since.
+    delivered: bool,
+}
+
+/// Stores origin fractions of material.
+///
+/// This is a `BTreeMap` without the `std` feature.
+#[cfg(feature = "std")]
+pub type Origins = HashMap<ContainerId, f64>;
+/// Stores origin fractions of material.
+#[cfg(not(feature = "std"))]
+pub type Origins = BTreeMap<ContainerId, f64>;
+
+/// Tracks which containers material originated from.
+#[derive(Clone)]
+struct Provenance {
+    /// Origin fractions of material in each container.
+    containers: Vec<Origins>,
+    /// Origin fractions of material moved by each grabber.
+    grabbers: Vec<Origins>,
+    /// Origin fractions of material waiting to be delivered to each container.
+    pending: Vec<Origins>,
+}
+
+/// Refers to material being put into a container, for provenance tracking.
+#[derive(Clone, Copy)]
+enum Material<'a> {
+    /// Material moved by grabbers, with the volume delivered by each.
+    Grabbers(&'a [(GrabberId, f64)]),
+    /// Material waiting to be delivered to a container.
+    Pending(ContainerId),
+    /// Material flowing into a container from the outside.
+    Inflow(ContainerId),
+}
+
+impl Provenance {
+    /// Attributes a volume of material to a single origin.
+    fn origin(id: ContainerId, volume: f64) -> Origins {
+        let mut origins = Origins::new();
+        if volume > 0.0 {origins.insert(id, 1.0);}
+        origins
+    }
+
+    /// Blends `v` volume of incoming material into existing origins.
+    fn blend(origins: &mut Origins, volume: f64, incoming: &Origins, v: f64) {
+        let total = volume + v;
+        if v <= 0.0 || total <= 0.0 {return}
+        for x in origins.values_mut() {
+            *x *= volume / total;
+        }
+        for (&id, &x) in incoming {
+            *origins.entry(id).or_insert(0.0) += x * v / total;
+        }
+    }
+}
+
+/// The result of advancing the timer of a grabber.
+pub(crate) enum Advance {
+    /// Part of the volume is delivered while transporting continuously.
+    Deliver(f64),
+    /// The transport completed, delivering the remaining volume.
+    Complete {
+        /// The volume left to deliver.
+        volume: f64,
+        /// The volume moved by the transport.
+        moved: f64,
+    },
+}
+
+/// Adds a group of grabbers to disjoint groups, merging groups that share a grabber.
+fn merge_group(groups: &mut Vec<Vec<GrabberId>>, gids: &[GrabberId]) {
+    let mut group: Vec<GrabberId> = vec![];
+    let mut i = 0;
+    while i < groups.len() {
+        if groups[i].iter().any(|gid| gids.contains(gid)) {
+            group.extend(groups.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    for &gid in gids {
+        if !group.contains(&gid) {group.push(gid)}
+    }
+    if group.len() > 1 {groups.push(group)}
+}
+
+/// Advances the timer of a grabber by a time delta,
+/// returning the volume it delivers, if any.
+///
+/// The timer of a frozen grabber does not advance.
+///
+/// This only touches the grabber state, such that grabbers can be advanced independently.
+pub(crate) fn advance(g: &Grabber, s: &mut GrabberState, dt: f64) -> Option<Advance> {
+    if s.frozen {return None}
+    if s.time <= 0.0 && s.volume <= 0.0 {
+        if s.cooldown <= 0.0 && dt > 0.0 {s.streak = 0}
+        s.cooldown = (s.cooldown - dt).max(0.0);
+        return None;
+    }
+    if g.delivery == DeliveryMode::Continuous && s.time > dt {
+        let v = s.volume * dt / s.time;
+        s.volume -= v;
+        s.time -= dt;
+        return Some(Advance::Deliver(v));
+    }
+    if g.delivery == DeliveryMode::Lump && g.stages > 1 && s.time > dt {
+        let before = remaining_stages(g, s);
+        s.time -= dt;
+        let after = remaining_stages(g, s);
+        if after >= before {return None}
+        let v = s.volume * (before - after) / before;
+        s.volume -= v;
+        return Some(Advance::Deliver(v));
+    }
+    s.time -= dt;
+    if s.time > 0.0 {return None}
+    let volume = s.volume;
+    s.volume = 0.0;
+    s.time = 0.0;
+    s.cooldown = g.cooldown;
+    Some(Advance::Complete {volume, moved: s.moved})
+}
+
+/// The number of stages a staged transport has not yet delivered.
+fn remaining_stages(g: &Grabber, s: &GrabberState) -> f64 {
+    if s.duration <= 0.0 {return 1.0}
+    -floor(-(s.time * g.stages as f64 / s.duration - TIME_EPSILON))
+}
+
+/// The time until a transport delivers material or completes.
+fn time_to_delivery(g: &Grabber, s: &GrabberState) -> f64 {
+    if g.delivery == DeliveryMode::Continuous || g.stages <= 1 || s.duration <= 0.0 {
+        return s.time;
+    }
+    let stage = s.duration / g.stages as f64;
+    s.time - (remaining_stages(g, s) - 1.0) * stage
+}
+
+/// The tolerance for rounding errors from accumulating time deltas.
+///
+/// Times within this tolerance of each other are treated as equal
+/// when stepping up to a time or releasing scheduled events.
+pub(crate) const TIME_EPSILON: f64 = 1e-9;
+
+/// The resolution to which volumes and times are quantized when hashing.
+///
+/// Raw floats are not hashed on purpose,
+/// since values that compare equal can have different bits (e.g. `0.0` and `-0.0`)
+/// and tiny rounding errors would otherwise produce different hashes.
+pub const HASH_RESOLUTION: f64 = 1e-9;
+
+/// Rounds a value down to the nearest integer.
+///
+/// `f64::floor` requires the standard library.
+fn floor(x: f64) -> f64 {
+    // Values this large have no fractional part.
+    if !(-4.5e15..=4.5e15).contains(&x) {return x}
+    let t = x as i64 as f64;
+    if t > x {t - 1.0} else {t}
+}
+
+/// Computes the square root of a non-negative value.
+///
+/// `f64::sqrt` requires the standard library.
+fn sqrt(x: f64) -> f64 {
+    if x <= 0.0 || x.is_nan() {return if x == 0.0 {0.0} else {f64::NAN}}
+    if x == f64::INFINITY {return x}
+    // Halving the exponent gives an initial guess close to the root.
+    let mut y = f64::from_bits((x.to_bits() >> 1) + (1023 << 51));
+    // After one Newton step, the guess is at least the root and decreases towards it.
+    y = 0.5 * (y + x / y);
+    loop {
+        let next = 0.5 * (y + x / y);
+        if next >= y {return y}
+        y = next;
+    }
+}
+
+/// Rounds a value to the nearest multiple of the resolution.
+fn quantize(v: f64, resolution: f64) -> i64 {
+    let q = v / resolution;
+    (if q < 0.0 {q - 0.5} else {q + 0.5}) as i64
+}
+
+impl Hash for Environment {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_state(HASH_RESOLUTION, state);
+    }
+}
+
+impl Clone for Environment {
+    /// Clones the environment without the registered callbacks.
+    fn clone(&self) -> Environment {
+        Environment {
+            containers: self.containers.clone(),
+            grabbers: self.grabbers.clone(),
+            grabber_states: self.grabber_states.clone(),
+            provenance: self.provenance.clone(),
+            on_grab: vec![],
+            on_complete: vec![],
+            watermarks: vec![],
+            rng: self.rng.clone(),
+            sensor_rng: self.sensor_rng.clone(),
+            allowed_transfers: self.allowed_transfers.clone(),
+            saturation: self.saturation,
+            sunk: self.sunk.clone(),
+            total_work: self.total_work,
+            gravity: self.gravity,
+            total_leaked: self.total_leaked,
+            exchange: self.exchange,
+            delivery_cap: self.delivery_cap,
+            pending: self.pending.clone(),
+            removed: self.removed.clone(),
+            peaks: self.peaks.clone(),
+            fallback: self.fallback,
+            time: self.time,
+            inflows: self.inflows.clone(),
+            links: self.links.clone(),
+            shared: self.shared.clone(),
+            names: self.names.clone(),
+            log: self.log.clone(),
+            history: self.history.clone(),
+            undo: self.undo.clone(),
+            flow_model: self.flow_model.clone(),
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment {Environment::new()}
+}
+
+impl core::iter::FromIterator<f64> for Environment {
+    /// Creates an environment with a container for each volume.
+    fn from_iter<I: IntoIterator<Item = f64>>(iter: I) -> Environment {
+        let mut env = Environment::new();
+        env.extend(iter);
+        env
+    }
+}
+
+impl Extend<f64> for Environment {
+    /// Adds a container for each volume.
+    fn extend<I: IntoIterator<Item = f64>>(&mut self, iter: I) {
+        for v in iter {
+            self.add_container(Container::new(v));
+        }
+    }
+}
+
 impl Environment {
     /// Creates a new empty environment.
     pub fn new() -> Environment {
@@ -108,149 +1039,2601 @@ impl Environment {
             containers: vec![],
             grabbers: vec![],
             grabber_states: vec![],
+            provenance: None,
+            on_grab: vec![],
+            on_complete: vec![],
+            watermarks: vec![],
+            rng: Rng::new(0),
+            sensor_rng: Rng::sensor(0),
+            allowed_transfers: None,
+            saturation: None,
+            sunk: vec![],
+            total_work: 0.0,
+            gravity: 9.81,
+            total_leaked: 0.0,
+            exchange: EnvDiff::default(),
+            delivery_cap: None,
+            pending: vec![],
+            removed: vec![],
+            peaks: vec![],
+            fallback: None,
+            time: 0.0,
+            inflows: vec![],
+            links: vec![],
+            shared: vec![],
+            names: vec![],
+            log: None,
+            history: None,
+            undo: None,
+            flow_model: Arc::new(MixedFlow),
         }
     }
 
     /// Adds a new container to the environment.
     pub fn add_container(&mut self, c: Container) -> ContainerId {
         let id = self.containers.len();
+        if let Some(p) = &mut self.provenance {
+            p.containers.push(Provenance::origin(ContainerId(id), c.volume));
+            p.pending.push(Origins::new());
+        }
+        self.peaks.push(c.volume);
+        self.sunk.push(0.0);
         self.containers.push(c);
+        self.pending.push(0.0);
+        self.removed.push(false);
         ContainerId(id)
     }
 
+    /// Removes a container from the environment.
+    ///
+    /// The material in the container is discarded and counted as removed from the environment.
+    /// Container IDs stay valid, and grabbers drawing from the container find it empty.
+    /// Material delivered to a removed container, e.g. by a grabber that was transporting
+    /// toward it, goes to the fallback container if one is set and is lost otherwise,
+    /// see `set_fallback_container`.
+    pub fn remove_container(&mut self, c: ContainerId) {
+        self.exchange.removed += self.containers[c.0].volume + self.pending[c.0];
+        self.containers[c.0].volume = 0.0;
+        self.pending[c.0] = 0.0;
+        self.removed[c.0] = true;
+        if let Some(p) = &mut self.provenance {
+            p.containers[c.0] = Origins::new();
+            p.pending[c.0] = Origins::new();
+        }
+    }
+
+    /// Returns `true` if the container has been removed.
+    pub fn is_removed(&self, c: ContainerId) -> bool {
+        self.removed[c.0]
+    }
+
+    /// Sets the container receiving material delivered to removed containers.
+    ///
+    /// Without a fallback, or when the fallback is removed itself,
+    /// such material is lost and counted as removed from the environment.
+    pub fn set_fallback_container(&mut self, c: ContainerId) {
+        self.fallback = Some(c);
+    }
+
     /// Adds a new grabber to the environment.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the transfer between the containers is not allowed.
+    /// Use `try_add_grabber` to handle this case.
     pub fn add_grabber(&mut self, g: Grabber) -> GrabberId {
+        match self.try_add_grabber(g) {
+            Ok(id) => id,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Adds a new grabber to the environment,
+    /// if the transfer between the containers is allowed.
+    pub fn try_add_grabber(&mut self, g: Grabber) -> Result<GrabberId, DisallowedTransfer> {
+        if !self.is_transfer_allowed(g.source, g.target) {
+            return Err(DisallowedTransfer {source: g.source, target: g.target});
+        }
         let id = self.grabbers.len();
+        if let Some(p) = &mut self.provenance {
+            p.grabbers.push(Provenance::origin(g.source, 0.0));
+        }
         self.grabbers.push(g);
-        self.grabber_states.push(GrabberState {time: 0.0, volume: 0.0});
-        GrabberId(id)
+        self.grabber_states.push(GrabberState::default());
+        Ok(GrabberId(id))
     }
 
-    /// Activates a grabber, if not busy.
+    /// Allows grabbers to move material from source to target.
     ///
-    /// Returns `Ok(())` if the grabber was activated.
-    /// Returns `Err(())` if the grabber is busy.
-    pub fn grab(&mut self, gid: GrabberId) -> Result<(), ()> {
-        if self.grabber_states[gid.0].time == 0.0 {
-            let g = &self.grabbers[gid.0];
-            let v = g.volume;
-            let v2 = self.containers[g.source.0].take(v);
-            let s = &mut self.grabber_states[gid.0];
-            s.volume = v2;
-            s.time = g.time;
-            Ok(())
-        } else {
-            Err(())
+    /// By default, every transfer is allowed.
+    /// Once a transfer is allowed explicitly,
+    /// only allowed transfers can be connected by new grabbers.
+    /// Existing grabbers are not affected.
+    pub fn allow_transfer(&mut self, source: ContainerId, target: ContainerId) {
+        let allowed = self.allowed_transfers.get_or_insert_with(Vec::new);
+        if !allowed.contains(&(source, target)) {
+            allowed.push((source, target));
         }
     }
 
-    /// Updates the environment with a time delta.
-    pub fn update(&mut self, dt: f64) {
-        let n = self.grabbers.len();
-        for i in 0..n {
-            let s = &mut self.grabber_states[i];
-            s.time -= dt;
-            if s.time <= 0.0 {
-                let g = &self.grabbers[i];
-                self.containers[g.target.0].put(s.volume);
-                s.volume = 0.0;
-                s.time = 0.0;
-            }
+    /// Returns whether grabbers may move material from source to target.
+    pub fn is_transfer_allowed(&self, source: ContainerId, target: ContainerId) -> bool {
+        match &self.allowed_transfers {
+            None => true,
+            Some(allowed) => allowed.contains(&(source, target)),
         }
     }
 
-    /// The volume of a container.
-    pub fn volume_of_container(&self, c: ContainerId) -> f64 {
-        self.containers[c.0].0
+    /// Gives an existing container a name.
+    ///
+    /// A container can have multiple names, but each name refers to one container.
+    pub fn alias_container(&mut self, existing: ContainerId, name: &str) -> Result<(), NameTaken> {
+        if let Some(container) = self.container_by_name(name) {
+            return Err(NameTaken {name: name.into(), container});
+        }
+        self.names.push((name.into(), existing));
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Looks up a container by name.
+    pub fn container_by_name(&self, name: &str) -> Option<ContainerId> {
+        self.names.iter().find(|(n, _)| n == name).map(|&(_, c)| c)
+    }
 
-    #[test]
-    fn take_from_container() {
-        let mut a = Container(10.0);
-        a.take(2.0);
-        assert_eq!(a.0, 8.0);
+    /// Links grabbers such that grabbing one of them activates all of them.
+    ///
+    /// Groups that share a grabber are merged.
+    pub fn link_grabbers(&mut self, gids: &[GrabberId]) {
+        merge_group(&mut self.links, gids);
     }
 
-    #[test]
-    fn test_environment() {
-        let mut env = Environment::new();
-        let a = env.add_container(Container(10.0));
-        let b = env.add_container(Container(0.0));
-        let ab = env.add_grabber(Grabber {
-            source: a,
-            target: b,
-            time: 1.0,
-            volume: 2.0,
-        });
-        assert_eq!(env.volume_of_container(a), 10.0);
-        assert!(env.grab(ab).is_ok());
-        assert_eq!(env.volume_of_container(a), 8.0);
-        assert!(env.grab(ab).is_err());
-        assert_eq!(env.volume_of_container(a), 8.0);
-        env.update(0.5);
+    /// Returns the grabbers linked with a grabber, including the grabber itself.
+    pub fn linked_grabbers(&self, gid: GrabberId) -> Vec<GrabberId> {
+        match self.links.iter().find(|group| group.contains(&gid)) {
+            Some(group) => group.clone(),
+            None => vec![gid],
+        }
+    }
+
+    /// Makes grabbers share capacity, such that at most one of them is busy at a time.
+    ///
+    /// This models a single mover servicing several routes.
+    /// Groups that share a grabber are merged.
+    pub fn share_capacity(&mut self, gids: &[GrabberId]) {
+        merge_group(&mut self.shared, gids);
+    }
+
+    /// Returns the grabbers sharing capacity with a grabber, including the grabber itself.
+    pub fn capacity_sharing_grabbers(&self, gid: GrabberId) -> Vec<GrabberId> {
+        match self.shared.iter().find(|group| group.contains(&gid)) {
+            Some(group) => group.clone(),
+            None => vec![gid],
+        }
+    }
+
+    /// Activates a grabber, if not busy and its condition holds.
+    ///
+    /// A linked grabber is activated together with all grabbers it is linked with,
+    /// or none of them if any can not be activated, see `grab_atomic`.
+    ///
+    /// Returns `Ok(())` if the grabber was activated.
+    pub fn grab(&mut self, gid: GrabberId) -> Result<(), GrabError> {
+        if self.links.iter().any(|group| group.contains(&gid)) {
+            return self.grab_atomic(&self.linked_grabbers(gid));
+        }
+        self.check_grab(gid)?;
+        let rng = self.rng.clone();
+        let record = self.activate(gid);
+        self.fire_on_grab(&record);
+        self.undo = Some(Undo {records: vec![record], rng, delivered: false});
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("grab");
+        Ok(())
+    }
+
+    /// Activates all grabbers, or none if any of them can not be activated.
+    ///
+    /// Grabbers are activated in order, so a grabber sees the volumes
+    /// taken by the grabbers before it.
+    /// If one fails, the already activated grabbers are rolled back
+    /// and their taken volume is returned to the sources.
+    pub fn grab_atomic(&mut self, gids: &[GrabberId]) -> Result<(), GrabError> {
+        let rng = self.rng.clone();
+        let mut records = vec![];
+        for &gid in gids {
+            if let Err(err) = self.check_grab(gid) {
+                while let Some(record) = records.pop() {
+                    self.revert(record);
+                }
+                self.rng = rng;
+                return Err(err);
+            }
+            records.push(self.activate(gid));
+        }
+        for record in &records {
+            self.fire_on_grab(record);
+        }
+        self.undo = Some(Undo {records, rng, delivered: false});
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("grab_atomic");
+        Ok(())
+    }
+
+    /// Activates all grabbers drawing from a source that can be activated,
+    /// sharing the material of the source in proportion to their volumes.
+    ///
+    /// When the source holds enough for all grabbers, each takes its full volume.
+    /// Otherwise, the whole source is divided, which may not respect batch sizes.
+    ///
+    /// Grabbers are activated in order and checked again before each activation,
+    /// so at most one grabber of a group sharing capacity is activated.
+    /// Linked grabbers are activated together, or not at all, like by `grab`.
+    /// Returns the activated grabbers, including linked grabbers.
+    pub fn distribute(&mut self, source: ContainerId) -> Vec<GrabberId> {
+        let gids: Vec<GrabberId> = (0..self.grabbers.len()).map(GrabberId)
+            .filter(|&gid| self.grabbers[gid.0].source == source && self.check_grab(gid).is_ok())
+            .collect();
+        let total: f64 = gids.iter().map(|&gid| self.grabbers[gid.0].volume).sum();
+        let volume = self.containers[source.0].volume;
+        let amounts: Vec<f64> = gids.iter().map(|&gid| {
+            if total <= volume {self.grab_amount(gid)}
+            else {volume * self.grabbers[gid.0].volume / total}
+        }).collect();
+        let rng = self.rng.clone();
+        let mut records: Vec<GrabRecord> = vec![];
+        for &gid in &gids {
+            let group = if self.links.iter().any(|group| group.contains(&gid)) {
+                self.linked_grabbers(gid)
+            } else {
+                vec![gid]
+            };
+            let start = records.len();
+            for &member in &group {
+                if self.check_grab(member).is_err() {
+                    while records.len() > start {
+                        let record = records.pop().unwrap();
+                        self.revert(record);
+                    }
+                    break;
+                }
+                let record = match gids.iter().position(|&other| other == member) {
+                    Some(k) => self.activate_with(member, amounts[k]),
+                    None => self.activate(member),
+                };
+                records.push(record);
+            }
+        }
+        for record in &records {
+            self.fire_on_grab(record);
+        }
+        let activated = records.iter().map(|record| record.gid).collect();
+        if !records.is_empty() {
+            self.undo = Some(Undo {records, rng, delivered: false});
+        }
+        activated
+    }
+
+    /// Undoes the most recent grab, returning the taken material to the sources
+    /// and restoring the grabber states from before the grab, except for activation counts.
+    ///
+    /// A grab activating several grabbers, e.g. by `grab_atomic`, is undone as a whole.
+    /// This fails when a grabber has delivered material since the grab.
+    /// Callbacks and logged events of the grab are not undone.
+    pub fn undo_last_grab(&mut self) -> Result<(), UndoError> {
+        match &self.undo {
+            None => return Err(UndoError::NothingToUndo),
+            Some(undo) if undo.delivered => return Err(UndoError::Delivered),
+            Some(_) => {}
+        }
+        let Undo {mut records, rng, ..} = self.undo.take().unwrap();
+        while let Some(record) = records.pop() {
+            self.revert(record);
+        }
+        self.rng = rng;
+        Ok(())
+    }
+
+    /// Checks whether a grabber can be activated.
+    fn check_grab(&self, gid: GrabberId) -> Result<(), GrabError> {
+        if self.is_busy(gid) {return Err(GrabError::Busy)}
+        if let Some(group) = self.shared.iter().find(|group| group.contains(&gid)) {
+            if group.iter().any(|&other| self.is_busy(other)) {return Err(GrabError::SharedBusy)}
+        }
+        if let Some(cond) = self.grabbers[gid.0].condition {
+            if !cond.holds(self) {return Err(GrabError::ConditionUnmet)}
+        }
+        let g = &self.grabbers[gid.0];
+        if g.require_space {
+            let free = self.containers[g.target.0].capacity - self.projected_volume(g.target);
+            if self.grab_amount(gid) > free {return Err(GrabError::TargetFull)}
+        }
+        Ok(())
+    }
+
+    /// Activates a grabber without checking whether it can be activated.
+    fn activate(&mut self, gid: GrabberId) -> GrabRecord {
+        let v = self.grab_amount(gid);
+        self.activate_with(gid, v)
+    }
+
+    /// Activates a grabber taking some volume, without checking whether it can be activated.
+    fn activate_with(&mut self, gid: GrabberId, v: f64) -> GrabRecord {
+        let state = self.grabber_states[gid.0].clone();
+        let g = &self.grabbers[gid.0];
+        let taken = self.flow_model.take(&mut self.containers[g.source.0], v);
+        if self.containers[g.source.0].fixed {self.exchange.added += taken}
+        if let Some(p) = &mut self.provenance {
+            p.grabbers[gid.0] = p.containers[g.source.0].clone();
+        }
+        let mut time = g.time;
+        if g.jitter > 0.0 {
+            let u = self.rng.next_f64();
+            time = (time + g.jitter * (2.0 * u - 1.0)).max(0.0);
+        }
+        let s = &mut self.grabber_states[gid.0];
+        s.volume = taken;
+        s.moved = taken;
+        s.time = time;
+        s.duration = time;
+        s.activations += 1;
+        s.streak += 1;
+        let g = &mut self.grabbers[gid.0];
+        let volume = g.volume;
+        if g.wear_factor < 1.0 && g.volume > g.min_volume {
+            g.volume = (g.volume * g.wear_factor).max(g.min_volume);
+        }
+        GrabRecord {gid, state, taken, volume}
+    }
+
+    /// Reverts an activation that has not delivered anything yet.
+    fn revert(&mut self, record: GrabRecord) {
+        let source = self.grabbers[record.gid.0].source;
+        if self.containers[source.0].fixed {
+            self.exchange.added -= record.taken;
+        } else {
+            self.containers[source.0].volume += record.taken;
+        }
+        // Activations stay counted, since the grabber was activated.
+        let activations = self.grabber_states[record.gid.0].activations;
+        self.grabber_states[record.gid.0] = GrabberState {activations, ..record.state};
+        self.grabbers[record.gid.0].volume = record.volume;
+    }
+
+    /// Calls the callbacks registered for activation.
+    fn fire_on_grab(&mut self, record: &GrabRecord) {
+        self.record(EventKind::Grab, record.gid, record.taken);
+        for f in &mut self.on_grab {
+            f(record.gid, record.taken);
+        }
+    }
+
+    /// Returns whether a grabber is transporting material or cooling down.
+    pub fn is_busy(&self, gid: GrabberId) -> bool {
+        let s = &self.grabber_states[gid.0];
+        s.time > 0.0 || s.volume > 0.0 || s.cooldown > 0.0
+    }
+
+    /// The status of a grabber.
+    pub fn status(&self, gid: GrabberId) -> GrabberStatus {
+        let s = &self.grabber_states[gid.0];
+        if s.time > 0.0 || s.volume > 0.0 {
+            GrabberStatus::Transporting {remaining: s.time}
+        } else if s.cooldown > 0.0 {
+            GrabberStatus::CoolingDown {remaining: s.cooldown}
+        } else {
+            GrabberStatus::Idle
+        }
+    }
+
+    /// How long a grabber has been idle since its most recent transport and cooldown ended.
+    ///
+    /// Returns zero while the grabber is busy,
+    /// and the elapsed simulated time if the grabber has never completed a transport.
+    pub fn idle_duration(&self, gid: GrabberId) -> f64 {
+        if self.is_busy(gid) {return 0.0}
+        (self.time - self.grabber_states[gid.0].idle_since).max(0.0)
+    }
+
+    /// The volume a grabber would take from its source if activated now.
+    fn grab_amount(&self, gid: GrabberId) -> f64 {
+        let g = &self.grabbers[gid.0];
+        let warmup = g.ramp * (self.grabber_states[gid.0].streak + 1) as f64;
+        let v = (g.volume * warmup.min(1.0)).min(self.containers[g.source.0].volume);
+        match g.batch_size {
+            // Tolerate rounding errors when the volume is a whole number of batches.
+            Some(b) if b > 0.0 => floor(v / b + 1e-9) * b,
+            _ => v,
+        }
+    }
+
+    /// Lists the idle grabbers that would not move any material if activated now.
+    ///
+    /// A grab is ineffective when the grabber would take nothing from its source,
+    /// or when its target has no room left, counting material already on the way,
+    /// such that all delivered material would spill.
+    pub fn ineffective_grabs(&self) -> Vec<GrabberId> {
+        let saturation = self.saturation.unwrap_or(f64::INFINITY);
+        (0..self.grabbers.len()).map(GrabberId)
+            .filter(|&gid| !self.is_busy(gid))
+            .filter(|&gid| {
+                let target = self.grabbers[gid.0].target;
+                let max = saturation.min(self.containers[target.0].capacity);
+                self.grab_amount(gid) <= 0.0 || self.projected_volume(target) >= max
+            })
+            .collect()
+    }
+
+    /// Computes how the volume of each container would change,
+    /// if a grabber was activated now and its transport completed.
+    ///
+    /// The source decreases and the target increases by the moved volume.
+    /// Returns zero for every container if the grabber is busy.
+    pub fn grab_sensitivity(&self, gid: GrabberId) -> Vec<f64> {
+        let mut delta = vec![0.0; self.containers.len()];
+        if !self.is_busy(gid) {
+            let g = &self.grabbers[gid.0];
+            let v = self.grab_amount(gid);
+            delta[g.source.0] -= v;
+            delta[g.target.0] += v;
+        }
+        delta
+    }
+
+    /// Sets the transport time of every grabber.
+    ///
+    /// Transports already in flight keep their remaining time,
+    /// so the new time applies from the next activation.
+    ///
+    /// Panics if the time is not positive.
+    pub fn set_all_grabber_times(&mut self, time: f64) {
+        assert!(time > 0.0, "Grabber time must be positive");
+        for g in &mut self.grabbers {
+            g.time = time;
+        }
+    }
+
+    /// Pauses the timer of a grabber during updates, while other grabbers proceed.
+    ///
+    /// A frozen grabber keeps its material in flight and does not complete until thawed.
+    pub fn freeze_grabber(&mut self, gid: GrabberId) {
+        self.grabber_states[gid.0].frozen = true;
+    }
+
+    /// Resumes the timer of a frozen grabber.
+    pub fn thaw_grabber(&mut self, gid: GrabberId) {
+        self.grabber_states[gid.0].frozen = false;
+    }
+
+    /// Returns whether the timer of a grabber is paused.
+    pub fn is_frozen(&self, gid: GrabberId) -> bool {
+        self.grabber_states[gid.0].frozen
+    }
+
+    /// The number of times a grabber has been activated.
+    ///
+    /// Activations rolled back by `grab_atomic` or undone by `undo_last_grab` are still counted.
+    pub fn activation_count(&self, gid: GrabberId) -> u64 {
+        self.grabber_states[gid.0].activations
+    }
+
+    /// How far a grabber is through its transport, from 0.0 when just started to 1.0 when done.
+    ///
+    /// Returns 1.0 for a grabber that is not transporting,
+    /// including one that is cooling down or has never been activated.
+    pub fn progress(&self, gid: GrabberId) -> f64 {
+        let s = &self.grabber_states[gid.0];
+        if s.time <= 0.0 || s.duration <= 0.0 {return 1.0}
+        1.0 - s.time / s.duration
+    }
+
+    /// The number of updates with a time delta until all grabbers are idle,
+    /// when no more grabbers are activated.
+    ///
+    /// Grabbers cooling down after a transport count as busy.
+    /// Frozen grabbers are ignored, since they never become idle.
+    /// Returns 0 if all grabbers are already idle.
+    ///
+    /// Updates a copy of the environment with auto grabbers turned off,
+    /// so the count matches calling `update` when nothing is activated.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn steps_to_quiescence(&self, dt: f64) -> usize {
+        assert!(dt > 0.0, "Time delta must be positive");
+        let mut env = self.clone();
+        for g in &mut env.grabbers {g.auto = false}
+        let busy = |env: &Environment| (0..env.grabbers.len())
+            .any(|i| !env.grabber_states[i].frozen && env.is_busy(GrabberId(i)));
+        let mut steps = 0;
+        while busy(&env) {
+            env.step(dt);
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Updates the environment with a time delta,
+    /// returning the transitions of grabbers that were busy before or after the update.
+    ///
+    /// The time delta is subdivided internally where grabbers finish a transport or cooldown,
+    /// such that one large update gives the same result as many small ones.
+    ///
+    /// Panics if the time delta is negative or not finite.
+    pub fn update(&mut self, dt: f64) -> Vec<(GrabberId, Transition)> {
+        let busy = self.busy_mask();
+        self.step(dt);
+        self.transitions(&busy)
+    }
+
+    /// Marks the grabbers that are busy.
+    pub(crate) fn busy_mask(&self) -> Vec<bool> {
+        (0..self.grabbers.len()).map(|i| self.is_busy(GrabberId(i))).collect()
+    }
+
+    /// Compares which grabbers are busy now with which grabbers were busy before.
+    pub(crate) fn transitions(&self, before: &[bool]) -> Vec<(GrabberId, Transition)> {
+        before.iter().enumerate().filter_map(|(i, &was_busy)| {
+            let transition = match (was_busy, self.is_busy(GrabberId(i))) {
+                (false, false) => return None,
+                (false, true) => Transition::Started,
+                (true, true) => Transition::Continued,
+                (true, false) => Transition::Finished,
+            };
+            Some((GrabberId(i), transition))
+        }).collect()
+    }
+
+    /// Updates the environment with a time delta,
+    /// returning the grabbers that completed a transport.
+    pub(crate) fn step(&mut self, dt: f64) -> Vec<GrabberId> {
+        self.step_with(dt, Environment::advance_timers)
+    }
+
+    /// Updates the environment with a time delta, advancing grabber timers with a function.
+    ///
+    /// The time delta is subdivided where grabbers finish a transport or cooldown,
+    /// such that auto grabbers are activated again at the right time
+    /// and the result does not depend on the step size.
+    pub(crate) fn step_with(
+        &mut self,
+        dt: f64,
+        advance_timers: fn(&mut Environment, f64) -> Vec<(usize, Advance)>
+    ) -> Vec<GrabberId> {
+        // The time left would never reach zero, or time would run backward.
+        assert!(dt.is_finite() && dt >= 0.0, "Time delta must be finite and non-negative");
+        let mut completed = vec![];
+        let mut left = dt;
+        loop {
+            self.auto_grab();
+            let h = self.next_event().map_or(left, |t| t.min(left));
+            self.leak(h);
+            let advances = advance_timers(self, h);
+            completed.extend(self.finish_step(h, advances));
+            left -= h;
+            if left <= 0.0 {break}
+        }
+        self.release_pending();
+        self.check_watermarks();
+        self.record_history();
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("update");
+        completed
+    }
+
+    /// Advances the timers of all grabbers.
+    fn advance_timers(&mut self, dt: f64) -> Vec<(usize, Advance)> {
+        self.grabbers.iter().zip(self.grabber_states.iter_mut()).enumerate()
+            .filter_map(|(i, (g, s))| advance(g, s, dt).map(|a| (i, a)))
+            .collect()
+    }
+
+    /// The time until the next grabber finishes a transport or cooldown, if any.
+    fn next_event(&self) -> Option<f64> {
+        self.grabbers.iter().zip(&self.grabber_states)
+            .filter(|(_, s)| !s.frozen)
+            .map(|(g, s)| if s.time > 0.0 {time_to_delivery(g, s)}
+                else if s.volume > 0.0 {0.0} else {s.cooldown})
+            .filter(|&t| t > 0.0)
+            .fold(None, |min: Option<f64>, t| Some(min.map_or(t, |m| m.min(t))))
+    }
+
+    /// Activates idle auto grabbers that are not frozen, whose source is not empty
+    /// and whose demand threshold is not reached.
+    fn auto_grab(&mut self) {
+        for i in 0..self.grabbers.len() {
+            let gid = GrabberId(i);
+            let demanded = self.grabbers[i].demand_threshold
+                .iter().all(|&(c, level)| self.containers[c.0].volume < level);
+            if self.grabbers[i].auto && !self.grabber_states[i].frozen && demanded &&
+               self.grab_amount(gid) > 0.0
+            {
+                let _ = self.grab(gid);
+            }
+        }
+    }
+
+    /// Removes a fraction of the volume from every container at once.
+    ///
+    /// The removed material is discarded and counted as removed from the environment.
+    /// Unlike leaking, this is not applied per update.
+    ///
+    /// Panics if the fraction is not in the range `[0, 1]`.
+    pub fn decay(&mut self, fraction: f64) {
+        assert!((0.0..=1.0).contains(&fraction), "Decay fraction must be in [0, 1]");
+        for c in self.containers.iter_mut().filter(|c| !c.fixed) {
+            let v = c.take(c.volume * fraction);
+            self.exchange.removed += v;
+        }
+    }
+
+    /// Removes the volume leaked by containers over a time delta.
+    fn leak(&mut self, dt: f64) {
+        for c in &mut self.containers {
+            if c.leak_rate > 0.0 && !c.fixed {
+                let v = c.take(c.leak_rate * dt);
+                self.total_leaked += v;
+                self.exchange.removed += v;
+            }
+        }
+    }
+
+    /// Applies deliveries of advanced grabber timers, followed by scheduled inflows,
+    /// returning the grabbers that completed a transport.
+    ///
+    /// The deliveries are applied in two phases:
+    /// first the volume each grabber delivers is computed,
+    /// then the volumes are put into the targets at once.
+    /// This makes the result independent of the order of the grabbers.
+    fn finish_step(&mut self, dt: f64, advances: Vec<(usize, Advance)>) -> Vec<GrabberId> {
+        self.time += dt;
+        let mut completed = vec![];
+        let mut deliveries = vec![];
+        for (i, a) in advances {
+            match a {
+                Advance::Deliver(v) => deliveries.extend(self.deliver(GrabberId(i), v)),
+                Advance::Complete {volume, moved} => {
+                    deliveries.extend(self.deliver(GrabberId(i), volume));
+                    self.total_work += self.transport_work(GrabberId(i));
+                    let s = &mut self.grabber_states[i];
+                    s.idle_since = self.time + s.cooldown;
+                    self.record(EventKind::Complete, GrabberId(i), moved);
+                    for f in &mut self.on_complete {
+                        f(GrabberId(i), moved);
+                    }
+                    completed.push(GrabberId(i));
+                }
+            }
+        }
+        self.put_deliveries(&deliveries);
+        while let Some(&(at, target, amount)) = self.inflows.first() {
+            if at > self.time + TIME_EPSILON {break}
+            self.inflows.remove(0);
+            self.exchange.added += amount;
+            self.put_into(target, amount, Material::Inflow(target));
+        }
+        self.check_watermarks();
+        completed
+    }
+
+    /// The simulated time since the environment was created.
+    pub fn now(&self) -> f64 {
+        self.time
+    }
+
+    /// Schedules material to flow into a container from the outside.
+    ///
+    /// The material is put into the container by the update that reaches the time `at`.
+    /// Inflows scheduled for the same time are applied in the order they were scheduled.
+    pub fn schedule_inflow(&mut self, c: ContainerId, amount: f64, at: f64) {
+        let i = self.inflows.iter().position(|&(t, _, _)| t > at).unwrap_or(self.inflows.len());
+        self.inflows.insert(i, (at, c, amount));
+    }
+
+    /// The work done by a grabber moving material upwards,
+    /// for the volume of its current or most recent transport.
+    ///
+    /// Computed as `moved * max(0, target.height - source.height) * gravity`.
+    /// Moving material downwards takes no work.
+    pub fn transport_work(&self, gid: GrabberId) -> f64 {
+        let g = &self.grabbers[gid.0];
+        let dh = self.containers[g.target.0].height - self.containers[g.source.0].height;
+        self.grabber_states[gid.0].moved * dh.max(0.0) * self.gravity
+    }
+
+    /// The total volume in containers, in flight and waiting to be delivered.
+    ///
+    /// Material lost by leaking or discarded into sinks is not included,
+    /// see `total_leaked` and `total_sunk`.
+    pub fn total_volume(&self) -> f64 {
+        self.containers.iter().map(|c| c.volume).sum::<f64>() + self.in_flight_volume()
+    }
+
+    /// The total volume in flight or waiting to be delivered.
+    pub fn in_flight_volume(&self) -> f64 {
+        self.grabber_states.iter().map(|s| s.volume).sum::<f64>() +
+        self.pending.iter().sum::<f64>()
+    }
+
+    /// The number of busy grabbers and the total volume they carry.
+    ///
+    /// Grabbers cooling down count as busy, see `is_busy`.
+    /// Unlike `in_flight_volume`, material waiting to be delivered is not included.
+    pub fn in_flight_summary(&self) -> (usize, f64) {
+        (0..self.grabbers.len()).filter(|&i| self.is_busy(GrabberId(i)))
+            .fold((0, 0.0), |(n, v), i| (n + 1, v + self.grabber_states[i].volume))
+    }
+
+    /// The total volume exchanged with the outside since the environment was created.
+    ///
+    /// Use `EnvDiff::since` to get the exchange over a step.
+    pub fn exchange(&self) -> EnvDiff {
+        self.exchange
+    }
+
+    /// Computes how much the total volume deviates from
+    /// the total volume before a step plus the exchanged volume during the step.
+    ///
+    /// A residual that is not close to zero indicates
+    /// that material was created or destroyed by mistake.
+    pub fn mass_balance_residual(&self, before: f64, diff: &EnvDiff) -> f64 {
+        self.total_volume() - (before + diff.added - diff.removed)
+    }
+
+    /// Summarizes the environment state.
+    pub fn stats(&self) -> EnvStats {
+        EnvStats {
+            total_volume: self.total_volume(),
+            busy_grabbers: self.busy_mask().into_iter().filter(|&busy| busy).count(),
+            empty_containers: self.containers.iter().filter(|c| c.volume <= 0.0).count(),
+            in_flight_volume: self.in_flight_volume(),
+            time: self.time,
+        }
+    }
+
+    /// The variance of container volumes.
+    ///
+    /// Returns zero for an environment without containers.
+    pub fn volume_variance(&self) -> f64 {
+        let n = self.containers.len();
+        if n == 0 {return 0.0}
+        let mean = self.containers.iter().map(|c| c.volume).sum::<f64>() / n as f64;
+        self.containers.iter().map(|c| (c.volume - mean) * (c.volume - mean)).sum::<f64>() / n as f64
+    }
+
+    /// The Gini coefficient of container volumes.
+    ///
+    /// This is zero when material is evenly distributed,
+    /// approaching one when all material is in a single container.
+    /// Returns zero for an environment without containers or material,
+    /// and NaN if some volume is NaN.
+    pub fn volume_gini(&self) -> f64 {
+        let mut volumes: Vec<f64> = self.containers.iter().map(|c| c.volume).collect();
+        let n = volumes.len() as f64;
+        let total: f64 = volumes.iter().sum();
+        if total <= 0.0 {return 0.0}
+        volumes.sort_by(|a, b| a.total_cmp(b));
+        let weighted: f64 = volumes.iter().enumerate().map(|(i, v)| (i + 1) as f64 * v).sum();
+        2.0 * weighted / (n * total) - (n + 1.0) / n
+    }
+
+    /// The total volume lost by leaking containers since the statistics were reset.
+    pub fn total_leaked(&self) -> f64 {
+        self.total_leaked
+    }
+
+    /// The total volume discarded into sinks since the statistics were reset.
+    ///
+    /// This is the cumulative output of the system.
+    pub fn total_sunk(&self) -> f64 {
+        self.sunk.iter().sum()
+    }
+
+    /// The volume discarded into a sink container since the statistics were reset.
+    pub fn sunk_volume(&self, c: ContainerId) -> f64 {
+        self.sunk[c.0]
+    }
+
+    /// The total work done by completed transports since the statistics were reset.
+    ///
+    /// This is the cumulative energy expended, see `transport_work`.
+    pub fn total_work(&self) -> f64 {
+        self.total_work
+    }
+
+    /// Resets statistics accumulated over the run.
+    ///
+    /// The simulation state is not changed.
+    pub fn reset_statistics(&mut self) {
+        for sunk in &mut self.sunk {*sunk = 0.0}
+        self.total_leaked = 0.0;
+        self.total_work = 0.0;
+        for (peak, c) in self.peaks.iter_mut().zip(self.containers.iter()) {
+            *peak = c.volume;
+        }
+    }
+
+    /// The maximum volume a container has reached since it was added
+    /// or statistics were reset, see `reset_statistics`.
+    ///
+    /// Peaks are recorded when material is put into the container,
+    /// and the current volume is included in case it was changed directly.
+    pub fn peak_volume(&self, c: ContainerId) -> f64 {
+        self.peaks[c.0].max(self.containers[c.0].volume)
+    }
+
+    /// Seeds the random number generator used for stochastic behavior.
+    ///
+    /// Simulations with the same seed are reproducible.
+    /// This also seeds the sensor noise of `noisy_volume`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.sensor_rng = Rng::sensor(seed);
+    }
+
+    /// Registers a callback invoked when a grabber is activated.
+    ///
+    /// The callback receives the grabber ID and the volume taken from the source.
+    /// Callbacks run while the environment is being mutated,
+    /// so they must not access the environment, e.g. through shared ownership.
+    pub fn on_grab(&mut self, f: Hook) {
+        self.on_grab.push(f);
+    }
+
+    /// Registers a callback invoked when a grabber completes a transport.
+    ///
+    /// The callback receives the grabber ID and the total volume moved.
+    /// Callbacks run while the environment is being mutated,
+    /// so they must not access the environment, e.g. through shared ownership.
+    pub fn on_complete(&mut self, f: Hook) {
+        self.on_complete.push(f);
+    }
+
+    /// Registers a callback invoked when the volume of a container rises above a level.
+    ///
+    /// The callback receives the container ID and the volume.
+    /// It fires once per crossing, and again only after the volume has dropped
+    /// to the level or below.
+    /// Volumes are checked during updates, after deliveries.
+    /// Replaces any previous high watermark of the container.
+    /// Callbacks run while the environment is being mutated,
+    /// so they must not access the environment, e.g. through shared ownership.
+    pub fn set_high_watermark(&mut self, c: ContainerId, level: f64, callback: ContainerHook) {
+        self.watermarks.retain(|w| w.container != c);
+        let above = self.containers[c.0].volume > level;
+        self.watermarks.push(Watermark {container: c, level, above, callback});
+    }
+
+    /// Calls the callbacks of high watermarks that volumes have risen above.
+    fn check_watermarks(&mut self) {
+        let containers = &self.containers;
+        for w in &mut self.watermarks {
+            let v = containers[w.container.0].volume;
+            if !w.above && v > w.level {
+                w.above = true;
+                (w.callback)(w.container, v);
+            } else if w.above && v <= w.level {
+                w.above = false;
+            }
+        }
+    }
+
+    /// Delivers volume moved by a grabber, except the part consumed by reaction.
+    ///
+    /// When deliveries are limited, the volume waits until the end of the update.
+    /// Otherwise, returns the volume to put into the target, see `put_deliveries`.
+    fn deliver(&mut self, gid: GrabberId, v: f64) -> Option<(GrabberId, f64)> {
+        self.record(EventKind::Deliver, gid, v);
+        if let Some(undo) = &mut self.undo {
+            if undo.records.iter().any(|r| r.gid == gid) {undo.delivered = true}
+        }
+        let g = &self.grabbers[gid.0];
+        let target = g.target;
+        let consumed = v * g.reaction_rate;
+        self.exchange.removed += consumed;
+        let v = v - consumed;
+        if self.delivery_cap.is_some() {
+            if let Some(p) = &mut self.provenance {
+                let pending = self.pending[target.0];
+                Provenance::blend(&mut p.pending[target.0], pending, &p.grabbers[gid.0], v);
+            }
+            self.pending[target.0] += v;
+            None
+        } else {
+            Some((gid, v))
+        }
+    }
+
+    /// Puts volumes delivered by grabbers into their targets.
+    ///
+    /// The volumes delivered into the same container are put at once,
+    /// such that material spilling beyond the capacity is shared
+    /// in proportion to the delivered volumes.
+    fn put_deliveries(&mut self, deliveries: &[(GrabberId, f64)]) {
+        let mut targets: Vec<ContainerId> = vec![];
+        for &(gid, _) in deliveries {
+            let target = self.grabbers[gid.0].target;
+            if !targets.contains(&target) {targets.push(target)}
+        }
+        for target in targets {
+            let batch: Vec<(GrabberId, f64)> = deliveries.iter().cloned()
+                .filter(|&(gid, _)| self.grabbers[gid.0].target == target)
+                .collect();
+            let v = batch.iter().map(|&(_, v)| v).sum();
+            self.put_into(target, v, Material::Grabbers(&batch));
+        }
+    }
+
+    /// Delivers waiting volume, limited per container.
+    fn release_pending(&mut self) {
+        let cap = self.delivery_cap.unwrap_or(f64::INFINITY);
+        for i in 0..self.containers.len() {
+            let v = self.pending[i].min(cap);
+            if v > 0.0 {
+                self.pending[i] -= v;
+                self.put_into(ContainerId(i), v, Material::Pending(ContainerId(i)));
+            }
+        }
+    }
+
+    /// Puts volume into a container.
+    ///
+    /// Saturates at the maximum volume, if set.
+    /// Material beyond the capacity of the container spills.
+    /// Material put into a sink is discarded.
+    fn put_into(&mut self, target: ContainerId, v: f64, material: Material) {
+        let target = if !self.removed[target.0] {target} else {
+            match self.fallback {
+                Some(fallback) if !self.removed[fallback.0] => fallback,
+                _ => {
+                    self.exchange.removed += v;
+                    return;
+                }
+            }
+        };
+        let c = &mut self.containers[target.0];
+        if c.sink {
+            self.sunk[target.0] += v;
+            self.exchange.removed += v;
+            return;
+        }
+        if c.fixed {
+            self.exchange.removed += v;
+            return;
+        }
+        let volume = c.volume;
+        let max = self.saturation.unwrap_or(f64::INFINITY).min(c.capacity);
+        self.flow_model.put(c, v);
+        if max < f64::INFINITY {
+            // Saturate like `Container::put_saturating`.
+            c.volume = if c.volume.is_nan() {volume} else {c.volume.min(max)};
+            self.exchange.removed += v - (c.volume - volume);
+        }
+        self.peaks[target.0] = self.peaks[target.0].max(c.volume);
+        if let Some(p) = &mut self.provenance {
+            let v = c.volume - volume;
+            let mixed;
+            let incoming = match material {
+                Material::Grabbers(&[(gid, _)]) => &p.grabbers[gid.0],
+                Material::Grabbers(batch) => {
+                    let mut origins = Origins::new();
+                    let mut total = 0.0;
+                    for &(gid, v) in batch {
+                        Provenance::blend(&mut origins, total, &p.grabbers[gid.0], v);
+                        total += v;
+                    }
+                    mixed = origins;
+                    &mixed
+                }
+                Material::Pending(c) => &p.pending[c.0],
+                Material::Inflow(c) => {
+                    mixed = Provenance::origin(c, v);
+                    &mixed
+                }
+            };
+            Provenance::blend(&mut p.containers[target.0], volume, incoming, v);
+        }
+    }
+
+    /// Enables tracking of where material originated from.
+    ///
+    /// Material currently in a container is attributed to that container.
+    /// Material already moved by a grabber is attributed to its source.
+    /// Material waiting to be delivered is attributed to its target.
+    /// Material flowing in from the outside is attributed to the container it flows into.
+    pub fn track_provenance(&mut self) {
+        let containers = self.containers.iter().enumerate()
+            .map(|(i, c)| Provenance::origin(ContainerId(i), c.volume))
+            .collect();
+        let grabbers = self.grabbers.iter().zip(self.grabber_states.iter())
+            .map(|(g, s)| Provenance::origin(g.source, s.volume))
+            .collect();
+        let pending = self.pending.iter().enumerate()
+            .map(|(i, &v)| Provenance::origin(ContainerId(i), v))
+            .collect();
+        self.provenance = Some(Provenance {containers, grabbers, pending});
+    }
+
+    /// Returns the fractions of a container's contents that originated from each container.
+    ///
+    /// Returns an empty map when provenance is not tracked or the container is empty.
+    pub fn provenance(&self, c: ContainerId) -> Origins {
+        match &self.provenance {
+            Some(p) if self.containers[c.0].volume > 0.0 => p.containers[c.0].clone(),
+            _ => Origins::new(),
+        }
+    }
+
+    /// The number of containers.
+    pub fn num_containers(&self) -> usize {
+        self.containers.len()
+    }
+
+    /// The number of grabbers.
+    pub fn num_grabbers(&self) -> usize {
+        self.grabbers.len()
+    }
+
+    /// The volume of a container.
+    pub fn volume_of_container(&self, c: ContainerId) -> f64 {
+        self.containers[c.0].volume
+    }
+
+    /// The volumes of several containers, in the order they are listed.
+    ///
+    /// Fails with all the containers that do not exist, if any.
+    pub fn volumes_of(&self, ids: &[ContainerId]) -> Result<Vec<f64>, UnknownContainers> {
+        let unknown: Vec<ContainerId> = ids.iter().cloned()
+            .filter(|c| c.0 >= self.containers.len()).collect();
+        if !unknown.is_empty() {return Err(UnknownContainers {containers: unknown})}
+        Ok(ids.iter().map(|c| self.containers[c.0].volume).collect())
+    }
+
+    /// Reads the volume of a container like a noisy sensor,
+    /// adding Gaussian noise with a standard deviation.
+    ///
+    /// The noise is drawn from a seeded random number generator of its own, see `set_seed`,
+    /// so readings are reproducible and never affect the simulation,
+    /// e.g. the jitter of grabbers. Undoing a grab does not rewind the noise.
+    pub fn noisy_volume(&mut self, c: ContainerId, noise_std: f64) -> f64 {
+        self.containers[c.0].volume + noise_std * self.sensor_rng.next_gaussian()
+    }
+
+    /// The volume of a container plus the volume in flight or waiting to be delivered.
+    ///
+    /// This is the volume the container will have when
+    /// all active grabbers have completed their transport.
+    pub fn projected_volume(&self, c: ContainerId) -> f64 {
+        self.grabbers.iter().zip(self.grabber_states.iter())
+            .filter(|(g, _)| g.target == c)
+            .fold(self.containers[c.0].volume + self.pending[c.0], |v, (_, s)| v + s.volume)
+    }
+
+    /// The instantaneous rate of change of the volume of a container.
+    ///
+    /// Grabbers with continuous delivery that are transporting toward the container
+    /// add their remaining volume over their remaining time, less the consumed fraction,
+    /// while a non-empty container loses its leak rate.
+    /// Grabbers with lump delivery do not contribute, since their material
+    /// arrives all at once at the end of the transport.
+    /// Grabbers take material at activation, so outgoing transports do not contribute either.
+    /// Sinks, fixed and removed containers do not change and have zero derivative.
+    pub fn volume_derivative(&self, c: ContainerId) -> f64 {
+        let container = &self.containers[c.0];
+        if container.sink || container.fixed || self.removed[c.0] {return 0.0}
+        let incoming: f64 = self.grabbers.iter().zip(self.grabber_states.iter())
+            .filter(|(g, s)| g.target == c && g.delivery == DeliveryMode::Continuous &&
+                             s.time > 0.0 && !s.frozen)
+            .map(|(g, s)| s.volume / s.time * (1.0 - g.reaction_rate))
+            .sum();
+        let leak = if container.volume > 0.0 {container.leak_rate} else {0.0};
+        incoming - leak
+    }
+
+    /// Hashes the simulation state with volumes and times quantized to a resolution.
+    ///
+    /// The grabber configuration is not hashed.
+    fn hash_state<H: Hasher>(&self, resolution: f64, state: &mut H) {
+        self.containers.len().hash(state);
+        for (c, &pending) in self.containers.iter().zip(self.pending.iter()) {
+            quantize(c.volume, resolution).hash(state);
+            quantize(pending, resolution).hash(state);
+        }
+        self.grabber_states.len().hash(state);
+        for s in &self.grabber_states {
+            quantize(s.time, resolution).hash(state);
+            quantize(s.volume, resolution).hash(state);
+            quantize(s.cooldown, resolution).hash(state);
+        }
+    }
+
+    /// Computes a key for the simulation state,
+    /// with volumes and times bucketed to a resolution before hashing.
+    ///
+    /// States that only differ below the resolution get the same key,
+    /// which is useful e.g. for transposition tables when searching.
+    #[cfg(feature = "std")]
+    pub fn state_key(&self, resolution: f64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash_state(resolution, &mut hasher);
+        hasher.finish()
+    }
+
+    /// Dumps the simulation state as a flat record of named values.
+    ///
+    /// The keys are, for container `i` and grabber `j`:
+    ///
+    /// - `container.i.volume`: the volume of the container
+    /// - `container.i.pending`: the volume waiting to be delivered to the container
+    /// - `grabber.j.remaining`: the time left of the transport
+    /// - `grabber.j.volume`: the volume in flight
+    /// - `grabber.j.cooldown`: the cooldown left before the grabber can be activated
+    ///
+    /// Containers are listed before grabbers, in order of their ids.
+    pub fn state_record(&self) -> Vec<(String, f64)> {
+        let mut record = vec![];
+        for (i, (c, &pending)) in self.containers.iter().zip(self.pending.iter()).enumerate() {
+            record.push((format!("container.{}.volume", i), c.volume));
+            record.push((format!("container.{}.pending", i), pending));
+        }
+        for (j, s) in self.grabber_states.iter().enumerate() {
+            record.push((format!("grabber.{}.remaining", j), s.time));
+            record.push((format!("grabber.{}.volume", j), s.volume));
+            record.push((format!("grabber.{}.cooldown", j), s.cooldown));
+        }
+        record
+    }
+
+    /// Formats the environment as deterministic lines of text, e.g. for golden-file tests.
+    ///
+    /// The first line is the simulated time, followed by a line with the settings of the
+    /// environment, one line per container, one line per grabber and one line per grabber state,
+    /// in order of their ids.
+    /// Allowed transfers, linked and shared grabbers and scheduled inflows follow,
+    /// one per line, such that every value that affects the simulation is included.
+    /// Numbers have six decimals, so the text only changes when a value changes noticeably.
+    pub fn canonical_string(&self) -> String {
+        // Adding zero turns negative zero into zero.
+        fn num(v: f64) -> String {format!("{:.6}", v + 0.0)}
+        fn opt(v: Option<f64>) -> String {v.map_or(String::from("none"), num)}
+        fn ids(gids: &[GrabberId]) -> String {
+            gids.iter().map(|g| format!(" {}", g.0)).collect()
+        }
+
+        let mut text = format!("time {}\n", num(self.time));
+        text.push_str(&format!(
+            "environment saturation {} gravity {} delivery_cap {} fallback {} \
+             restricted {} rng {}\n",
+            opt(self.saturation), num(self.gravity), opt(self.delivery_cap),
+            self.fallback.map_or(String::from("none"), |c| format!("{}", c.0)),
+            self.allowed_transfers.is_some(), self.rng.state
+        ));
+        for (i, (c, &pending)) in self.containers.iter().zip(self.pending.iter()).enumerate() {
+            let unit = match &c.unit {
+                Some(unit) => format!("{:?}", unit),
+                None => String::from("none"),
+            };
+            text.push_str(&format!(
+                "container {} volume {} pending {} capacity {} leak_rate {} height {} \
+                 position {} {} unit {} unit_scale {} sink {} fixed {} removed {}\n",
+                i, num(c.volume), num(pending), num(c.capacity), num(c.leak_rate), num(c.height),
+                num(c.position[0]), num(c.position[1]), unit, num(c.unit_scale),
+                c.sink, c.fixed, self.removed[i]
+            ));
+        }
+        for (j, g) in self.grabbers.iter().enumerate() {
+            let delivery = match g.delivery {
+                DeliveryMode::Lump => "lump",
+                DeliveryMode::Continuous => "continuous",
+            };
+            let condition = match g.condition {
+                Some(Condition::Below(c, v)) => format!("below {} {}", c.0, num(v)),
+                Some(Condition::Above(c, v)) => format!("above {} {}", c.0, num(v)),
+                None => String::from("none"),
+            };
+            let demand = match g.demand_threshold {
+                Some((c, level)) => format!("{} {}", c.0, num(level)),
+                None => String::from("none"),
+            };
+            text.push_str(&format!(
+                "grabber {} {} -> {} volume {} time {} cooldown {} delivery {} auto {} \
+                 jitter {} condition {} batch_size {} require_space {} reaction_rate {} \
+                 wear_factor {} min_volume {} stages {} ramp {} demand_threshold {}\n",
+                j, g.source.0, g.target.0, num(g.volume), num(g.time), num(g.cooldown),
+                delivery, g.auto, num(g.jitter), condition, opt(g.batch_size), g.require_space,
+                num(g.reaction_rate), num(g.wear_factor), num(g.min_volume), g.stages,
+                num(g.ramp), demand
+            ));
+        }
+        for (j, s) in self.grabber_states.iter().enumerate() {
+            text.push_str(&format!(
+                "state {} remaining {} volume {} moved {} cooldown {} duration {} \
+                 activations {} streak {} idle_since {} frozen {}\n",
+                j, num(s.time), num(s.volume), num(s.moved), num(s.cooldown), num(s.duration),
+                s.activations, s.streak, num(s.idle_since), s.frozen
+            ));
+        }
+        for &(source, target) in self.allowed_transfers.iter().flatten() {
+            text.push_str(&format!("allow {} -> {}\n", source.0, target.0));
+        }
+        for group in &self.links {
+            text.push_str(&format!("link{}\n", ids(group)));
+        }
+        for group in &self.shared {
+            text.push_str(&format!("shared{}\n", ids(group)));
+        }
+        for &(at, c, amount) in &self.inflows {
+            text.push_str(&format!("inflow at {} container {} amount {}\n", num(at), c.0, num(amount)));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn take_from_container() {
+        let mut a = Container::new(10.0);
+        a.take(2.0);
+        assert_eq!(a.volume, 8.0);
+    }
+
+    #[test]
+    fn test_environment() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        assert_eq!(env.volume_of_container(a), 10.0);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.volume_of_container(a), 8.0);
+        assert!(env.grab(ab).is_err());
+        assert_eq!(env.volume_of_container(a), 8.0);
+        env.update(0.5);
+        assert!(env.grab(ab).is_err());
+        env.update(0.5);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.volume_of_container(a), 6.0);
+    }
+
+    #[test]
+    fn test_environment_remainder() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        assert_eq!(env.volume_of_container(a), 1.0);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        env.update(0.5);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        env.update(0.5);
+        assert_eq!(env.volume_of_container(b), 1.0);
+    }
+
+    #[test]
+    fn test_environment_chain() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert!(env.grab(ab).is_ok());
+
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.volume_of_container(b), 1.0);
+        assert_eq!(env.volume_of_container(c), 0.0);
+
+        assert!(env.grab(bc).is_ok());
+
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        assert_eq!(env.volume_of_container(c), 1.0);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(1.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.track_provenance();
+        assert_eq!(env.provenance(b), Origins::new());
+        assert!(env.grab(ab).is_ok());
+        env.update(1.0);
+        assert_eq!(env.provenance(b)[&a], 1.0);
+        assert!(env.grab(bc).is_ok());
+        env.update(1.0);
+        let p = env.provenance(c);
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[&a], 0.5);
+        assert_eq!(p[&c], 0.5);
+    }
+
+    #[test]
+    fn test_continuous_delivery() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0)
+            .delivery(DeliveryMode::Continuous));
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.volume_of_container(a), 8.0);
+        env.update(0.25);
+        assert_eq!(env.volume_of_container(b), 0.5);
+        env.update(0.25);
+        assert_eq!(env.volume_of_container(b), 1.0);
         assert!(env.grab(ab).is_err());
         env.update(0.5);
+        assert_eq!(env.volume_of_container(b), 2.0);
+        assert!(env.grab(ab).is_ok());
+    }
+
+    #[test]
+    fn test_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(3.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let grabs = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(Mutex::new(0.0));
+        let grabs2 = grabs.clone();
+        env.on_grab(Box::new(move |_, _| {grabs2.fetch_add(1, Ordering::Relaxed);}));
+        let completed2 = completed.clone();
+        env.on_complete(Box::new(move |gid, v| {
+            assert_eq!(gid, ab);
+            *completed2.lock().unwrap() += v;
+        }));
+        for _ in 0..3 {
+            let _ = env.grab(ab);
+            env.update(0.5);
+        }
+        env.update(1.0);
+        env.update(1.0);
+        assert_eq!(grabs.load(Ordering::Relaxed), 2);
+        assert_eq!(*completed.lock().unwrap(), 3.0);
+    }
+
+    #[test]
+    fn test_high_watermark() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 2.0, 1.0));
+        let alarms = Arc::new(AtomicUsize::new(0));
+        let alarms2 = alarms.clone();
+        env.set_high_watermark(b, 2.5, Box::new(move |c, v| {
+            assert_eq!(c, b);
+            assert!(v > 2.5);
+            alarms2.fetch_add(1, Ordering::Relaxed);
+        }));
+        for _ in 0..4 {
+            env.grab(ab).unwrap();
+            env.update(1.0);
+        }
+        assert_eq!(env.volume_of_container(b), 4.0);
+        assert_eq!(alarms.load(Ordering::Relaxed), 1);
+        env.grab(ba).unwrap();
+        env.update(1.0);
+        assert_eq!(alarms.load(Ordering::Relaxed), 1);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 3.0);
+        assert_eq!(alarms.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_environment_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        env.on_grab(Box::new(|_, _| {}));
+        env.set_high_watermark(a, 2.0, Box::new(|_, _| {}));
+        assert_send(&env);
+        assert_send(&Batch::new(vec![env]));
+    }
+
+    #[test]
+    fn test_idle_duration() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0).cooldown(0.5));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        env.update(2.0);
+        assert_eq!(env.idle_duration(ab), 2.0);
+        env.grab(ab).unwrap();
+        env.update(0.5);
+        assert_eq!(env.idle_duration(ab), 0.0);
+        env.update(1.0);
+        assert_eq!(env.idle_duration(ab), 0.0);
+        env.update(1.0);
+        assert_eq!(env.idle_duration(ab), 1.0);
+        env.update(2.0);
+        assert_eq!(env.idle_duration(ab), 3.0);
+        assert_eq!(env.idle_duration(ba), 6.5);
+    }
+
+    #[test]
+    fn test_volumes_of() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        env.add_container(Container::new(2.0));
+        let c = env.add_container(Container::new(3.0));
+        assert_eq!(env.volumes_of(&[c, a]), Ok(vec![3.0, 1.0]));
+        assert_eq!(env.volumes_of(&[]), Ok(vec![]));
+        let err = env.volumes_of(&[ContainerId(5), a, ContainerId(3)]).unwrap_err();
+        assert_eq!(err.containers, vec![ContainerId(5), ContainerId(3)]);
+        assert_eq!(format!("{}", err), "Unknown containers 5, 3");
+    }
+
+    #[test]
+    fn test_noisy_volume() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        env.set_seed(5);
+        let mut env2 = env.clone();
+        let reading = env.noisy_volume(a, 0.5);
+        assert_eq!(reading, 10.112010538405944);
+        assert_eq!(env2.noisy_volume(a, 0.5), reading);
+        assert_ne!(env.noisy_volume(a, 0.5), reading);
+        assert_eq!(env.volume_of_container(a), 10.0);
+        assert_eq!(env.noisy_volume(a, 0.0), 10.0);
+    }
+
+    #[test]
+    fn test_noisy_volume_keeps_jitter() {
+        fn run(reads: usize) -> f64 {
+            let mut env = Environment::new();
+            let a = env.add_container(Container::new(10.0));
+            let b = env.add_container(Container::new(0.0));
+            let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0).jitter(0.5));
+            env.set_seed(7);
+            for _ in 0..reads {
+                env.noisy_volume(a, 1.0);
+            }
+            env.grab(ab).unwrap();
+            env.grabber_states[ab.0].time
+        }
+
+        assert_eq!(run(3), run(0));
+        assert_ne!(run(0), 1.0);
+    }
+
+    #[test]
+    fn test_simultaneous_deliveries() {
+        fn fixture(reversed: bool) -> Environment {
+            let mut env = Environment::new();
+            let a = env.add_container(Container::new(1.0));
+            let b = env.add_container(Container::new(1.0));
+            let c = env.add_container(Container::new(0.0).capacity(1.0));
+            let mut sources = [a, b];
+            if reversed {sources.reverse()}
+            for &source in &sources {
+                env.add_grabber(Grabber::new(source, c, 1.0, 1.0));
+            }
+            env.track_provenance();
+            env.grab(GrabberId(0)).unwrap();
+            env.grab(GrabberId(1)).unwrap();
+            env.update(1.0);
+            env
+        }
+
+        let c = ContainerId(2);
+        let env = fixture(false);
+        let env2 = fixture(true);
+        assert_eq!(env.volume_of_container(c), 1.0);
+        assert_eq!(env2.volume_of_container(c), 1.0);
+        assert_eq!(env.provenance(c), env2.provenance(c));
+        assert_eq!(env.provenance(c)[&ContainerId(0)], 0.5);
+        assert_eq!(env.provenance(c)[&ContainerId(1)], 0.5);
+    }
+
+    #[test]
+    fn test_projected_volume() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.5));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.projected_volume(b), 0.5);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.volume_of_container(b), 0.5);
+        assert_eq!(env.projected_volume(b), 1.5);
+        assert_eq!(env.projected_volume(a), 0.0);
+        env.update(1.0);
+        assert_eq!(env.projected_volume(b), 1.5);
+        assert_eq!(env.projected_volume(c), 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_state_key() {
+        fn chain(v: f64) -> Environment {
+            let mut env = Environment::new();
+            let a = env.add_container(Container::new(v));
+            let b = env.add_container(Container::new(0.0));
+            let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+            assert!(env.grab(ab).is_ok());
+            env
+        }
+
+        let a = chain(2.0);
+        let b = chain(2.0001);
+        assert_eq!(a.state_key(0.01), b.state_key(0.01));
+        assert_ne!(a.state_key(1e-6), b.state_key(1e-6));
+        assert_ne!(a.state_key(0.01), chain(3.0).state_key(0.01));
+
+        let mut c = chain(2.0);
+        c.update(0.5);
+        assert_ne!(a.state_key(0.01), c.state_key(0.01));
+
+        let hash = |env: &Environment| {
+            let mut hasher = DefaultHasher::new();
+            env.hash(&mut hasher);
+            hasher.finish()
+        };
+        let mut d = chain(2.0);
+        d.containers[1].volume = -0.0;
+        assert_eq!(hash(&a), hash(&d));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0).jitter(0.25));
+        env.set_seed(42);
+        let mut times = vec![];
+        for _ in 0..3 {
+            assert!(env.grab(ab).is_ok());
+            times.push(env.grabber_states[ab.0].time);
+            env.update(2.0);
+        }
+        assert_eq!(times, vec![1.1207824393859116, 0.82995519643846, 0.8893005651275694]);
+        for &t in &times {
+            assert!((0.75..=1.25).contains(&t));
+        }
+
+        env.set_seed(42);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.grabber_states[ab.0].time, times[0]);
+    }
+
+    #[test]
+    fn test_allowed_transfers() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        assert!(env.is_transfer_allowed(a, c));
+        env.allow_transfer(a, b);
+        assert!(env.try_add_grabber(Grabber::new(a, b, 1.0, 1.0)).is_ok());
+        assert_eq!(env.try_add_grabber(Grabber::new(a, c, 1.0, 1.0)).err(),
+                   Some(DisallowedTransfer {source: a, target: c}));
+        assert!(env.try_add_grabber(Grabber::new(b, a, 1.0, 1.0)).is_err());
+        assert_eq!(env.grabbers.len(), 1);
+        assert_eq!(env.grabber_states.len(), 1);
+    }
+
+    #[test]
+    fn test_grab_sensitivity() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(0.5));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.grab_sensitivity(ab), vec![-0.5, 0.5, 0.0]);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.grab_sensitivity(ab), vec![0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0).cooldown(1.0));
+        assert_eq!(env.status(ab), GrabberStatus::Idle);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.status(ab), GrabberStatus::Transporting {remaining: 1.0});
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 2.0);
+        assert_eq!(env.status(ab), GrabberStatus::CoolingDown {remaining: 1.0});
+        assert!(env.is_busy(ab));
+        assert!(env.grab(ab).is_err());
+        env.update(0.5);
+        assert_eq!(env.status(ab), GrabberStatus::CoolingDown {remaining: 0.5});
+        assert!(env.grab(ab).is_err());
+        assert_eq!(env.volume_of_container(b), 2.0);
+        env.update(0.5);
+        assert_eq!(env.status(ab), GrabberStatus::Idle);
+        assert!(env.grab(ab).is_ok());
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(f64::MAX));
+        let b = env.add_container(Container::new(f64::MAX));
+        let ab = env.add_grabber(Grabber::new(a, b, f64::MAX, 1.0));
+        env.saturation = Some(1e300);
+        assert!(env.grab(ab).is_ok());
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 1e300);
+
+        let mut c = Container::new(1.0);
+        c.put_saturating(f64::INFINITY, 10.0);
+        assert_eq!(c.volume, 10.0);
+        c.put_saturating(f64::NAN, 10.0);
+        assert_eq!(c.volume, 10.0);
+    }
+
+    #[test]
+    fn test_total_sunk() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(5.0));
+        let b = env.add_container(Container::new(0.0).sink());
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        for _ in 0..4 {
+            assert!(env.grab(ab).is_ok());
+            env.update(1.0);
+        }
+        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        assert_eq!(env.total_sunk(), 5.0);
+        assert_eq!(env.sunk_volume(b), 5.0);
+        assert_eq!(env.sunk_volume(a), 0.0);
+        env.reset_statistics();
+        assert_eq!(env.total_sunk(), 0.0);
+        assert_eq!(env.sunk_volume(b), 0.0);
+    }
+
+    #[test]
+    fn test_condition() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(2.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0)
+            .condition(Condition::Below(b, 2.0)));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.grab(ab), Err(GrabError::ConditionUnmet));
+        assert_eq!(env.volume_of_container(a), 10.0);
+        assert!(env.grab(bc).is_ok());
+        assert_eq!(env.grab(bc), Err(GrabError::Busy));
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.volume_of_container(a), 9.0);
+    }
+
+    #[test]
+    fn test_transport_work() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).height(3.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 2.0, 1.0));
+        env.gravity = 10.0;
+        assert_eq!(env.transport_work(ab), 0.0);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.transport_work(ab), 60.0);
+        env.update(1.0);
+        assert!(env.grab(ba).is_ok());
+        assert_eq!(env.transport_work(ba), 0.0);
+    }
+
+    #[test]
+    fn test_total_work() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).height(3.0));
+        let c = env.add_container(Container::new(0.0).height(1.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 1.0, 2.0));
+        env.gravity = 10.0;
+        assert!(env.grab(ab).is_ok());
+        assert!(env.grab(ac).is_ok());
+        env.update(1.0);
+        assert_eq!(env.total_work(), 60.0);
+        assert!(env.grab(ab).is_ok());
+        assert!(env.grab(bc).is_ok());
+        env.update(1.0);
+        assert_eq!(env.total_work(), 130.0);
+        env.reset_statistics();
+        assert_eq!(env.total_work(), 0.0);
+    }
+
+    #[test]
+    fn test_stages() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 3.0, 3.0).stages(3));
+        assert!(env.grab(ab).is_ok());
+        env.update(0.5);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        env.update(0.5);
+        assert_eq!(env.volume_of_container(b), 1.0);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 2.0);
+        assert!(env.is_busy(ab));
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 3.0);
+        assert!(!env.is_busy(ab));
+
+        env.enable_event_log();
+        assert!(env.grab(ab).is_ok());
+        env.update(3.0);
+        assert_eq!(env.volume_of_container(b), 6.0);
+        let deliveries = env.event_log().iter().filter(|e| e.kind == EventKind::Deliver).count();
+        assert_eq!(deliveries, 3);
+    }
+
+    #[test]
+    fn test_ineffective_grabs() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(5.0).capacity(5.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 1.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        assert_eq!(env.ineffective_grabs(), vec![bc, ac, ba]);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.ineffective_grabs(), vec![bc, ac, ba]);
+        env.update(1.0);
+        assert_eq!(env.ineffective_grabs(), vec![bc, ac]);
+    }
+
+    #[test]
+    fn test_ramp() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(20.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0).ramp(0.25));
+        for expected in [1.0, 3.0, 6.0] {
+            assert!(env.grab(ab).is_ok());
+            env.update(1.0);
+            assert_eq!(env.volume_of_container(b), expected);
+        }
+        env.update(1.0);
+        assert!(env.grab(ab).is_ok());
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 7.0);
+    }
+
+    #[test]
+    fn test_grab_atomic() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(5.0));
+        let c = env.add_container(Container::new(0.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        let bc2 = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert!(env.grab(bc2).is_ok());
+        assert_eq!(env.grab_atomic(&[ac, bc, bc2]), Err(GrabError::Busy));
+        assert_eq!(env.volume_of_container(a), 10.0);
+        assert_eq!(env.volume_of_container(b), 4.0);
+        assert!(!env.is_busy(ac));
+        assert!(!env.is_busy(bc));
+        assert_eq!(env.grab_atomic(&[ac, ac]), Err(GrabError::Busy));
+        assert!(!env.is_busy(ac));
+        assert!(env.grab_atomic(&[ac, bc]).is_ok());
+        assert_eq!(env.volume_of_container(a), 8.0);
+        assert_eq!(env.volume_of_container(b), 3.0);
+    }
+
+    #[test]
+    fn test_leak() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(2.0).leak_rate(0.5));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.total_volume(), 2.0);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(a), 0.5);
+        assert_eq!(env.total_volume(), 1.5);
+        assert_eq!(env.total_leaked(), 0.5);
+        env.update(0.5);
+        assert_eq!(env.volume_of_container(a), 0.25);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.total_leaked(), 1.0);
+        assert_eq!(env.total_volume() + env.total_leaked(), 2.0);
+    }
+
+    #[test]
+    fn test_mass_balance_residual() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(3.0).leak_rate(0.25));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0).sink());
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 0.5, 0.5)
+            .delivery(DeliveryMode::Continuous));
+        for _ in 0..4 {
+            let before = env.total_volume();
+            let exchange = env.exchange();
+            let _ = env.grab(ab);
+            let _ = env.grab(bc);
+            env.update(0.5);
+            let diff = env.exchange().since(&exchange);
+            assert!(env.mass_balance_residual(before, &diff).abs() < 1e-12);
+        }
+        assert!(env.exchange().removed > 0.0);
+
+        let before = env.total_volume();
+        let exchange = env.exchange();
+        env.update(0.5);
+        env.containers[b.0].volume += 1.0;
+        let diff = env.exchange().since(&exchange);
+        assert!((env.mass_balance_residual(before, &diff) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_batch_size() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.3));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0).batch_size(0.5));
         assert!(env.grab(ab).is_ok());
-        assert_eq!(env.volume_of_container(a), 6.0);
+        assert_eq!(env.grabber_states[ab.0].volume, 1.0);
+        assert!((env.volume_of_container(a) - 0.3).abs() < 1e-12);
+        env.update(1.0);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.grabber_states[ab.0].volume, 0.0);
+        assert_eq!(env.volume_of_container(b), 1.0);
     }
 
     #[test]
-    fn test_environment_remainder() {
+    fn test_delivery_cap() {
         let mut env = Environment::new();
-        let a = env.add_container(Container(1.0));
-        let b = env.add_container(Container(0.0));
-        let ab = env.add_grabber(Grabber {
-            source: a,
-            target: b,
-            time: 1.0,
-            volume: 2.0,
-        });
+        let a = env.add_container(Container::new(5.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 5.0, 1.0));
+        env.delivery_cap = Some(2.0);
+        env.track_provenance();
+        assert!(env.grab(ab).is_ok());
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 2.0);
+        assert_eq!(env.projected_volume(b), 5.0);
+        assert_eq!(env.total_volume(), 5.0);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 4.0);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 5.0);
+        assert_eq!(env.total_volume(), 5.0);
+        assert_eq!(env.provenance(b)[&a], 1.0);
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut env = Environment::new();
+        assert_eq!(env.num_containers(), 0);
+        assert_eq!(env.num_grabbers(), 0);
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        assert_eq!(env.num_containers(), 3);
+        assert_eq!(env.num_grabbers(), 2);
+    }
+
+    #[test]
+    fn test_require_space() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(1.0).capacity(2.5));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0).require_space());
+        let ab2 = env.add_grabber(Grabber::new(a, b, 1.0, 1.0).require_space());
+        let ab3 = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(env.grab(ab2), Err(GrabError::TargetFull));
+        assert_eq!(env.volume_of_container(a), 9.0);
+        assert!(env.grab(ab3).is_ok());
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 2.5);
+        assert_eq!(env.exchange().removed, 0.5);
+    }
+
+    #[test]
+    fn test_state_record() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.grab(ab).unwrap();
+        env.update(0.5);
+        let record = env.state_record();
+        assert_eq!(record.len(), 10);
+        assert_eq!(record[0], ("container.0.volume".to_string(), 8.0));
+        assert_eq!(record[3], ("container.1.pending".to_string(), 0.0));
+        assert!(record.contains(&("grabber.1.remaining".to_string(), 0.5)));
+        assert!(record.contains(&("grabber.1.volume".to_string(), 2.0)));
+        assert!(record.contains(&("grabber.0.cooldown".to_string(), 0.0)));
+    }
+
+    #[test]
+    fn test_schedule_inflow() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        env.schedule_inflow(a, 2.0, 3.0);
+        env.schedule_inflow(a, 4.0, 2.0);
+        env.update(1.0);
         assert_eq!(env.volume_of_container(a), 1.0);
+        env.update(0.5);
+        assert_eq!(env.volume_of_container(a), 1.0);
+        env.update(0.5);
+        assert_eq!(env.now(), 2.0);
+        assert_eq!(env.volume_of_container(a), 5.0);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(a), 7.0);
+        assert_eq!(env.exchange().added, 6.0);
+    }
+
+    #[test]
+    fn test_volume_imbalance() {
+        let env = Environment::new();
+        assert_eq!(env.volume_variance(), 0.0);
+        assert_eq!(env.volume_gini(), 0.0);
+
+        let mut balanced = Environment::new();
+        let mut skewed = Environment::new();
+        for _ in 0..4 {
+            balanced.add_container(Container::new(2.5));
+        }
+        skewed.add_container(Container::new(10.0));
+        for _ in 0..3 {
+            skewed.add_container(Container::new(0.0));
+        }
+        assert_eq!(balanced.volume_variance(), 0.0);
+        assert_eq!(balanced.volume_gini(), 0.0);
+        assert_eq!(skewed.volume_variance(), 18.75);
+        assert_eq!(skewed.volume_gini(), 0.75);
+
+        skewed.containers[1].volume = f64::NAN;
+        assert!(skewed.volume_gini().is_nan());
+    }
+
+    #[test]
+    fn test_collect_containers() {
+        let mut env: Environment = vec![10.0, 0.0, 0.0].into_iter().collect();
+        assert_eq!(env.num_containers(), 3);
+        assert_eq!(env.volume_of_container(ContainerId(0)), 10.0);
+        env.extend(vec![2.5]);
+        assert_eq!(env.num_containers(), 4);
+        assert_eq!(env.volume_of_container(ContainerId(3)), 2.5);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut env: Environment = vec![10.0, 5.0, 0.0].into_iter().collect();
+        env.decay(0.1);
+        assert_eq!(env.volume_of_container(ContainerId(0)), 9.0);
+        assert_eq!(env.volume_of_container(ContainerId(1)), 4.5);
+        assert_eq!(env.volume_of_container(ContainerId(2)), 0.0);
+        assert_eq!(env.exchange().removed, 1.5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_decay_invalid_fraction() {
+        let mut env: Environment = vec![10.0].into_iter().collect();
+        env.decay(1.5);
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ab2 = env.add_grabber(Grabber::new(a, b, 1.0, 2.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.grab(ab).unwrap();
+        env.grab(ab2).unwrap();
+        assert_eq!(env.update(1.0), vec![
+            (ab, Transition::Finished),
+            (ab2, Transition::Continued),
+        ]);
+        assert_eq!(env.update(1.0), vec![(ab2, Transition::Finished)]);
+        assert_eq!(env.update(1.0), vec![]);
+    }
+
+    #[test]
+    fn test_transitions_auto() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 2.0).auto());
+        assert_eq!(env.update(1.0), vec![(ab, Transition::Started)]);
+        assert_eq!(env.update(0.5), vec![(ab, Transition::Continued)]);
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 2.0));
+        assert_eq!(env.progress(ab), 1.0);
+        env.grab(ab).unwrap();
+        assert_eq!(env.progress(ab), 0.0);
+        env.update(1.0);
+        assert!((env.progress(ab) - 0.5).abs() < 1e-9);
+        env.update(1.0);
+        assert_eq!(env.progress(ab), 1.0);
+    }
+
+    #[test]
+    fn test_steps_to_quiescence() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 0.7));
+        let ab2 = env.add_grabber(Grabber::new(a, b, 1.0, 0.2).cooldown(0.5));
+        assert_eq!(env.steps_to_quiescence(0.3), 0);
+        env.grab(ab).unwrap();
+        assert_eq!(env.steps_to_quiescence(0.3), 3);
+        env.grab(ab2).unwrap();
+        assert_eq!(env.steps_to_quiescence(0.3), 3);
+        for _ in 0..3 {
+            env.update(0.3);
+        }
+        assert!(!env.is_busy(ab) && !env.is_busy(ab2));
+
+        let ab3 = env.add_grabber(Grabber::new(a, b, 1.0, 0.7).cooldown(0.5));
+        env.grab(ab3).unwrap();
+        let predicted = env.steps_to_quiescence(0.3);
+        let mut steps = 0;
+        while env.is_busy(ab3) {
+            env.update(0.3);
+            steps += 1;
+        }
+        assert_eq!(predicted, steps);
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn test_link_grabbers() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0).condition(Condition::Above(b, 5.0)));
+        env.link_grabbers(&[ab, ac]);
+        assert_eq!(env.linked_grabbers(ac), vec![ab, ac]);
+        assert!(env.grab(ac).is_ok());
+        assert!(env.is_busy(ab));
+        assert_eq!(env.volume_of_container(a), 7.0);
+        env.update(1.0);
+
+        env.link_grabbers(&[ac, bc]);
+        assert_eq!(env.linked_grabbers(bc), vec![ab, ac, bc]);
+        assert_eq!(env.grab(ab), Err(GrabError::ConditionUnmet));
+        assert!(!env.is_busy(ab) && !env.is_busy(ac));
+        assert_eq!(env.volume_of_container(a), 7.0);
+    }
+
+    #[test]
+    fn test_share_capacity() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 1.0, 2.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.share_capacity(&[ab, ac]);
+        assert_eq!(env.capacity_sharing_grabbers(ab), vec![ab, ac]);
+        assert_eq!(env.capacity_sharing_grabbers(bc), vec![bc]);
+        assert!(env.grab(ac).is_ok());
+        assert_eq!(env.grab(ab), Err(GrabError::SharedBusy));
+        env.update(1.0);
+        assert_eq!(env.grab(ab), Err(GrabError::SharedBusy));
+        assert!(env.grab(bc).is_ok());
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(c), 1.0);
         assert!(env.grab(ab).is_ok());
-        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.grab(ac), Err(GrabError::SharedBusy));
+    }
+
+    #[test]
+    fn test_auto_grab() {
+        fn fixture() -> Environment {
+            let mut env = Environment::new();
+            let a = env.add_container(Container::new(10.0));
+            let b = env.add_container(Container::new(0.0));
+            let c = env.add_container(Container::new(0.0));
+            env.add_grabber(Grabber::new(a, b, 1.0, 0.5).auto());
+            env.add_grabber(Grabber::new(b, c, 0.75, 0.25).cooldown(0.5).auto()
+                .delivery(DeliveryMode::Continuous));
+            env
+        }
+
+        let mut coarse = fixture();
+        let mut fine = fixture();
+        coarse.update(3.0);
+        for _ in 0..12 {
+            fine.update(0.25);
+        }
+        assert_eq!(coarse.to_bytes(), fine.to_bytes());
+        assert_eq!(coarse.volume_of_container(ContainerId(0)), 4.0);
+        assert_eq!(coarse.volume_of_container(ContainerId(2)), 3.0);
+    }
+
+    #[test]
+    fn test_demand_threshold() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0).auto().demand_threshold(b, 2.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 2.0, 1.0));
+        env.update(5.0);
+        assert!(!env.is_busy(ab));
+        assert_eq!(env.volume_of_container(b), 2.0);
+        assert_eq!(env.volume_of_container(a), 8.0);
+
+        // Draining the target resumes the flow.
+        env.grab(bc).unwrap();
         assert_eq!(env.volume_of_container(b), 0.0);
+        env.update(5.0);
+        assert!(!env.is_busy(ab));
+        assert_eq!(env.volume_of_container(b), 2.0);
+        assert_eq!(env.volume_of_container(a), 6.0);
+        assert_eq!(env.volume_of_container(c), 2.0);
+    }
+
+    #[test]
+    fn test_fallback_container() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(1.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.grab(ab).unwrap();
         env.update(0.5);
-        assert_eq!(env.volume_of_container(b), 0.0);
+        env.remove_container(b);
+        assert!(env.is_removed(b));
+        assert_eq!(env.exchange().removed, 1.0);
         env.update(0.5);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        assert_eq!(env.exchange().removed, 3.0);
+
+        env.set_fallback_container(c);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 0.0);
+        assert_eq!(env.volume_of_container(c), 2.0);
+        assert_eq!(env.volume_of_container(a), 6.0);
+        assert_eq!(env.exchange().removed, 3.0);
+    }
+
+    #[test]
+    fn test_volume_derivative() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(1.0).leak_rate(0.5));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 2.0).delivery(DeliveryMode::Continuous));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        assert_eq!(env.volume_derivative(b), -0.5);
+        env.grab(ab).unwrap();
+        assert_eq!(env.volume_derivative(b), 1.5);
+        env.update(1.0);
+        assert_eq!(env.volume_derivative(b), 1.5);
+
+        // Lump deliveries do not contribute until they arrive.
+        env.grab(ba).unwrap();
+        assert_eq!(env.volume_derivative(a), 0.0);
+        env.freeze_grabber(ab);
+        assert_eq!(env.volume_derivative(b), -0.5);
+    }
+
+    #[test]
+    fn test_peak_volume() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(1.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 4.0, 1.0));
+        assert_eq!(env.peak_volume(a), 10.0);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        env.grab(bc).unwrap();
+        env.update(1.0);
         assert_eq!(env.volume_of_container(b), 1.0);
+        assert_eq!(env.peak_volume(b), 5.0);
+        assert_eq!(env.peak_volume(c), 4.0);
+        env.reset_statistics();
+        assert_eq!(env.peak_volume(b), 1.0);
     }
 
     #[test]
-    fn test_environment_chain() {
+    fn test_container_from() {
+        let c = Container::from(2.5);
+        assert_eq!(c.volume, 2.5);
+        assert!(!c.sink);
+    }
+
+    #[test]
+    fn test_fixed_container() {
         let mut env = Environment::new();
-        let a = env.add_container(Container(1.0));
-        let b = env.add_container(Container(0.0));
-        let c = env.add_container(Container(0.0));
-        let ab = env.add_grabber(Grabber {
-            source: a,
-            target: b,
-            time: 1.0,
-            volume: 1.0
-        });
-        let bc = env.add_grabber(Grabber {
-            source: b,
-            target: c,
-            time: 1.0,
-            volume: 1.0,
+        let a = env.add_container(Container::new(5.0).fixed().leak_rate(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0).auto());
+        env.add_grabber(Grabber::new(b, a, 1.0, 0.5).auto());
+        let before = env.total_volume();
+        env.update(10.0);
+        assert_eq!(env.volume_of_container(a), 5.0);
+        assert_eq!(env.activation_count(ab), 10);
+        assert!(env.mass_balance_residual(before, &env.exchange()).abs() < 1e-9);
+
+        env.decay(0.5);
+        assert_eq!(env.volume_of_container(a), 5.0);
+        env.containers[a.0].put_saturating(2.0, 100.0);
+        assert_eq!(env.volume_of_container(a), 5.0);
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0).sink());
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 0.5).delivery(DeliveryMode::Continuous));
+        env.grab(ab).unwrap();
+        env.update(0.25);
+        assert_eq!(env.canonical_string(), "\
+time 0.250000
+environment saturation none gravity 9.810000 delivery_cap none fallback none restricted \
+false rng 0
+container 0 volume 8.000000 pending 0.000000 capacity inf leak_rate 0.000000 height 0.000000 \
+position 0.000000 0.000000 unit none unit_scale 1.000000 sink false fixed false removed \
+false
+container 1 volume 0.000000 pending 0.000000 capacity inf leak_rate 0.000000 height 0.000000 \
+position 0.000000 0.000000 unit none unit_scale 1.000000 sink false fixed false removed \
+false
+container 2 volume 0.000000 pending 0.000000 capacity inf leak_rate 0.000000 height 0.000000 \
+position 0.000000 0.000000 unit none unit_scale 1.000000 sink true fixed false removed false
+grabber 0 0 -> 1 volume 2.000000 time 1.000000 cooldown 0.000000 delivery lump auto false \
+jitter 0.000000 condition none batch_size none require_space false reaction_rate 0.000000 \
+wear_factor 1.000000 min_volume 0.000000 stages 1 ramp 1.000000 demand_threshold none
+grabber 1 1 -> 2 volume 1.000000 time 0.500000 cooldown 0.000000 delivery continuous auto \
+false jitter 0.000000 condition none batch_size none require_space false reaction_rate \
+0.000000 wear_factor 1.000000 min_volume 0.000000 stages 1 ramp 1.000000 demand_threshold \
+none
+state 0 remaining 0.750000 volume 2.000000 moved 2.000000 cooldown 0.000000 duration \
+1.000000 activations 1 streak 1 idle_since 0.000000 frozen false
+state 1 remaining 0.000000 volume 0.000000 moved 0.000000 cooldown 0.000000 duration \
+0.000000 activations 0 streak 0 idle_since 0.000000 frozen false
+");
+        assert_eq!(env.clone().canonical_string(), env.canonical_string());
+    }
+
+    #[test]
+    fn test_canonical_string_settings() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(4.0).unit("L", 2.0).position(1.0, -2.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0)
+            .jitter(0.1).condition(Condition::Above(a, 1.0)).batch_size(0.5).require_space()
+            .reaction_rate(0.25).wear(0.9, 0.2).stages(2).ramp(0.5).demand_threshold(b, 3.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        env.saturation = Some(100.0);
+        env.gravity = 1.5;
+        env.delivery_cap = Some(2.0);
+        env.set_fallback_container(a);
+        env.allow_transfer(a, b);
+        env.link_grabbers(&[ab, ba]);
+        env.share_capacity(&[ab, ba]);
+        env.schedule_inflow(b, 1.0, 2.0);
+        assert_eq!(env.canonical_string(), "\
+time 0.000000
+environment saturation 100.000000 gravity 1.500000 delivery_cap 2.000000 fallback 0 \
+restricted true rng 0
+container 0 volume 4.000000 pending 0.000000 capacity inf leak_rate 0.000000 height 0.000000 \
+position 1.000000 -2.000000 unit \"L\" unit_scale 2.000000 sink false fixed false removed \
+false
+container 1 volume 0.000000 pending 0.000000 capacity inf leak_rate 0.000000 height 0.000000 \
+position 0.000000 0.000000 unit none unit_scale 1.000000 sink false fixed false removed \
+false
+grabber 0 0 -> 1 volume 1.000000 time 1.000000 cooldown 0.000000 delivery lump auto false \
+jitter 0.100000 condition above 0 1.000000 batch_size 0.500000 require_space true \
+reaction_rate 0.250000 wear_factor 0.900000 min_volume 0.200000 stages 2 ramp 0.500000 \
+demand_threshold 1 3.000000
+grabber 1 1 -> 0 volume 1.000000 time 1.000000 cooldown 0.000000 delivery lump auto false \
+jitter 0.000000 condition none batch_size none require_space false reaction_rate 0.000000 \
+wear_factor 1.000000 min_volume 0.000000 stages 1 ramp 1.000000 demand_threshold none
+state 0 remaining 0.000000 volume 0.000000 moved 0.000000 cooldown 0.000000 duration \
+0.000000 activations 0 streak 0 idle_since 0.000000 frozen false
+state 1 remaining 0.000000 volume 0.000000 moved 0.000000 cooldown 0.000000 duration \
+0.000000 activations 0 streak 0 idle_since 0.000000 frozen false
+allow 0 -> 1
+link 0 1
+shared 0 1
+inflow at 2.000000 container 1 amount 1.000000
+");
+    }
+
+    #[test]
+    #[should_panic(expected = "Time delta must be finite and non-negative")]
+    fn test_update_nan() {
+        let mut env = Environment::new();
+        env.update(f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "Time delta must be finite and non-negative")]
+    fn test_update_negative() {
+        let mut env = Environment::new();
+        env.add_container(Container::new(5.0).leak_rate(1.0));
+        env.update(-3.0);
+    }
+
+    #[test]
+    fn test_in_flight_summary() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let ab2 = env.add_grabber(Grabber::new(a, b, 1.5, 2.0).cooldown(1.0));
+        env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        assert_eq!(env.in_flight_summary(), (0, 0.0));
+        env.grab(ab).unwrap();
+        env.grab(ab2).unwrap();
+        assert_eq!(env.in_flight_summary(), (2, 3.5));
+        env.update(2.0);
+        assert_eq!(env.in_flight_summary(), (1, 0.0));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.grab(ab).unwrap();
+        env.update(0.5);
+        assert_eq!(env.stats(), EnvStats {
+            total_volume: 10.0,
+            busy_grabbers: 1,
+            empty_containers: 2,
+            in_flight_volume: 2.0,
+            time: 0.5,
         });
-        assert!(env.grab(ab).is_ok());
+    }
 
+    #[test]
+    fn test_container_names() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        env.alias_container(a, "well").unwrap();
+        env.alias_container(b, "tank").unwrap();
+        env.alias_container(b, "reservoir").unwrap();
+        assert_eq!(env.alias_container(a, "tank"),
+                   Err(NameTaken {name: "tank".into(), container: b}));
+        assert_eq!(env.container_by_name("reservoir"), Some(b));
+        assert_eq!(env.container_by_name("pond"), None);
+
+        let source = env.container_by_name("well").unwrap();
+        let target = env.container_by_name("tank").unwrap();
+        let g = env.add_grabber(Grabber::new(source, target, 2.0, 1.0));
+        env.grab(g).unwrap();
         env.update(1.0);
-        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.volume_of_container(b), 2.0);
+    }
+
+    #[test]
+    fn test_activation_count() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0).condition(Condition::Above(b, 5.0)));
+        for _ in 0..3 {
+            env.grab(ab).unwrap();
+            assert!(env.grab(ab).is_err());
+            env.update(1.0);
+        }
+        assert_eq!(env.activation_count(ab), 3);
+        assert!(env.grab_atomic(&[ab, ba]).is_err());
+        assert_eq!(env.activation_count(ab), 4);
+        assert_eq!(env.activation_count(ba), 0);
+    }
+
+    #[test]
+    fn test_reaction_rate() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 5.0, 1.0).reaction_rate(0.2));
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 4.0);
+        assert_eq!(env.exchange().removed, 1.0);
+        assert_eq!(env.mass_balance_residual(10.0, &env.exchange()), 0.0);
+    }
+
+    #[test]
+    fn test_freeze_grabber() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ab2 = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.grab(ab).unwrap();
+        env.grab(ab2).unwrap();
+        env.freeze_grabber(ab2);
+        assert!(env.is_frozen(ab2));
+        env.update(5.0);
+        assert!(!env.is_busy(ab));
+        assert!(env.is_busy(ab2));
+        assert_eq!(env.grabber_states[ab2.0].time, 1.0);
         assert_eq!(env.volume_of_container(b), 1.0);
-        assert_eq!(env.volume_of_container(c), 0.0);
+        assert_eq!(env.steps_to_quiescence(1.0), 0);
 
-        assert!(env.grab(bc).is_ok());
+        env.thaw_grabber(ab2);
+        env.update(1.0);
+        assert_eq!(env.volume_of_container(b), 3.0);
+    }
+
+    #[test]
+    fn test_undo_last_grab() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        assert_eq!(env.undo_last_grab(), Err(UndoError::NothingToUndo));
+        env.grab(ab).unwrap();
+        env.update(0.5);
+        assert_eq!(env.undo_last_grab(), Ok(()));
+        assert_eq!(env.volume_of_container(a), 10.0);
+        assert!(!env.is_busy(ab));
+        assert_eq!(env.activation_count(ab), 1);
+        assert_eq!(env.undo_last_grab(), Err(UndoError::NothingToUndo));
+
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.undo_last_grab(), Err(UndoError::Delivered));
+        assert_eq!(env.volume_of_container(b), 2.0);
+    }
 
+    #[test]
+    fn test_set_all_grabber_times() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 0.5));
+        env.grab(ab).unwrap();
+        env.set_all_grabber_times(2.0);
+        assert_eq!(env.grabber_states[ab.0].time, 1.0);
         env.update(1.0);
+        env.grab(ab).unwrap();
+        env.grab(ba).unwrap();
+        env.update(1.0);
+        assert!(env.is_busy(ab) && env.is_busy(ba));
+        env.update(1.0);
+        assert!(!env.is_busy(ab) && !env.is_busy(ba));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_all_grabber_times_invalid() {
+        let mut env = Environment::new();
+        env.set_all_grabber_times(0.0);
+    }
+
+    #[test]
+    fn test_distribute() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(3.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 4.0, 1.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        assert_eq!(env.distribute(a), vec![ab, ac]);
+        assert!(!env.is_busy(ba));
         assert_eq!(env.volume_of_container(a), 0.0);
-        assert_eq!(env.volume_of_container(b), 0.0);
-        assert_eq!(env.volume_of_container(c), 1.0);
+        assert_eq!(env.grabber_states[ab.0].volume, 1.0);
+        assert_eq!(env.grabber_states[ac.0].volume, 2.0);
+        assert_eq!(env.distribute(a), vec![]);
+
+        env.update(1.0);
+        env.containers[a.0].volume = 3.0;
+        env.grabbers[ab.0].volume = 1.0;
+        env.grabbers[ac.0].volume = 2.0;
+        assert_eq!(env.distribute(a), vec![ab, ac]);
+        assert_eq!(env.grabber_states[ab.0].volume, 1.0);
+        assert_eq!(env.grabber_states[ac.0].volume, 2.0);
+    }
+
+    #[test]
+    fn test_distribute_groups() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(4.0));
+        let b = env.add_container(Container::new(1.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.share_capacity(&[ab, ac]);
+        assert_eq!(env.distribute(a), vec![ab]);
+        assert!(!env.is_busy(ac));
+        assert_eq!(env.volume_of_container(a), 2.0);
+
+        env.update(1.0);
+        env.link_grabbers(&[ab, bc]);
+        assert_eq!(env.distribute(a), vec![ab, bc]);
+        assert!(env.is_busy(bc));
+        assert!(!env.is_busy(ac));
+        env.undo_last_grab().unwrap();
+        assert_eq!(env.volume_of_container(a), 2.0);
+        assert_eq!(env.volume_of_container(b), 3.0);
+    }
+
+    #[test]
+    fn test_wear() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(100.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 4.0, 1.0).wear(0.5, 1.5));
+        let mut moved = vec![];
+        for _ in 0..4 {
+            env.grab(ab).unwrap();
+            moved.push(env.grabber_states[ab.0].moved);
+            env.update(1.0);
+        }
+        assert_eq!(moved, vec![4.0, 2.0, 1.5, 1.5]);
+
+        env.grabbers[ab.0].min_volume = 0.0;
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        env.grab(ab).unwrap();
+        assert_eq!(env.grabbers[ab.0].volume, 0.375);
+        env.undo_last_grab().unwrap();
+        assert_eq!(env.grabbers[ab.0].volume, 0.75);
     }
-}
 
+    #[test]
+    fn test_display_unit() {
+        assert_eq!(format!("{}", Container::new(8.0).unit("L", 100.0)), "800 L");
+        assert_eq!(format!("{}", Container::new(8.0)), "8");
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(8.0).unit("kg", 0.5));
+        env.containers[a.0].take(2.0);
+        assert_eq!(env.containers[a.0].to_string(), "3 kg");
+    }
+}
@@ -0,0 +1,229 @@
+//! Opt-in log of events happening in the environment.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{ContainerId, Environment, GrabberId};
+
+/// Describes what happened in an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// A grabber was activated, taking volume from its source.
+    Grab,
+    /// A grabber delivered volume to its target.
+    Deliver,
+    /// A grabber completed a transport, having moved the volume in total.
+    Complete,
+}
+
+/// Stores something that happened to a grabber.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    /// The simulated time of the event.
+    pub time: f64,
+    /// The grabber.
+    pub grabber: GrabberId,
+    /// What happened.
+    pub kind: EventKind,
+    /// The volume involved.
+    pub volume: f64,
+}
+
+impl Environment {
+    /// Enables logging of events.
+    pub fn enable_event_log(&mut self) {
+        if self.log.is_none() {self.log = Some(vec![])}
+    }
+
+    /// Returns the logged events in the order they happened.
+    ///
+    /// Returns an empty list when events are not logged.
+    pub fn event_log(&self) -> &[Event] {
+        match &self.log {
+            Some(log) => log,
+            None => &[],
+        }
+    }
+
+    /// Logs an event at the current time, if events are logged.
+    pub(crate) fn record(&mut self, kind: EventKind, grabber: GrabberId, volume: f64) {
+        if let Some(log) = &mut self.log {
+            log.push(Event {time: self.time, grabber, kind, volume});
+        }
+    }
+
+    /// The volume delivered by a grabber within the last `window` time units.
+    ///
+    /// Deliveries exactly `window` time units ago are not included.
+    /// This is computed from the event log, so it is zero when events are not logged.
+    pub fn windowed_throughput(&self, gid: GrabberId, window: f64) -> f64 {
+        let since = self.time - window;
+        self.event_log().iter()
+            .filter(|e| e.kind == EventKind::Deliver && e.grabber == gid && e.time > since)
+            .map(|e| e.volume)
+            .sum()
+    }
+
+    /// Lists the containers that grabbers repeatedly found empty
+    /// within the last `window` time units.
+    ///
+    /// A container is starved when at least two activations of grabbers drawing from it
+    /// took nothing in the window, and no activation took any material.
+    /// This is computed from the event log, so it is empty when events are not logged.
+    pub fn starved_containers(&self, window: f64) -> Vec<ContainerId> {
+        let since = self.time - window;
+        let mut empty_grabs = vec![0; self.containers.len()];
+        let mut supplied = vec![false; self.containers.len()];
+        for e in self.event_log() {
+            if e.kind != EventKind::Grab || e.time <= since {continue}
+            let source = self.grabbers[e.grabber.0].source;
+            if e.volume > 0.0 {supplied[source.0] = true} else {empty_grabs[source.0] += 1}
+        }
+        (0..self.containers.len())
+            .filter(|&i| empty_grabs[i] >= 2 && !supplied[i])
+            .map(ContainerId)
+            .collect()
+    }
+
+    /// Lists each completed transport as `(grabber, start_time, end_time)`, e.g. for a Gantt chart.
+    ///
+    /// Transports are ordered by their start time.
+    /// Transports still in progress are not included.
+    /// This is computed from the event log, so it is empty when events are not logged.
+    pub fn timeline(&self) -> Vec<(GrabberId, f64, f64)> {
+        let mut started: Vec<Option<(usize, f64)>> = vec![None; self.grabbers.len()];
+        let mut entries = vec![];
+        for e in self.event_log() {
+            match e.kind {
+                EventKind::Grab => {
+                    started[e.grabber.0] = Some((entries.len(), e.time));
+                    entries.push(None);
+                }
+                EventKind::Complete => if let Some((i, start)) = started[e.grabber.0].take() {
+                    entries[i] = Some((e.grabber, start, e.time));
+                }
+                EventKind::Deliver => {}
+            }
+        }
+        entries.into_iter().flatten().collect()
+    }
+
+    /// The volume delivered into a container minus the volume taken from it by grabbers.
+    ///
+    /// This is computed from the event log, so it is zero when events are not logged.
+    pub fn net_delivered(&self, c: ContainerId) -> f64 {
+        self.event_log().iter().map(|e| {
+            let g = &self.grabbers[e.grabber.0];
+            match e.kind {
+                EventKind::Deliver if g.target == c => e.volume,
+                EventKind::Grab if g.source == c => -e.volume,
+                _ => 0.0,
+            }
+        }).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_event_log() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert!(env.event_log().is_empty());
+
+        env.enable_event_log();
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.event_log(), &[
+            Event {time: 1.0, grabber: ab, kind: EventKind::Grab, volume: 2.0},
+            Event {time: 2.0, grabber: ab, kind: EventKind::Deliver, volume: 2.0},
+            Event {time: 2.0, grabber: ab, kind: EventKind::Complete, volume: 2.0},
+        ]);
+    }
+
+    #[test]
+    fn test_windowed_throughput() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.enable_event_log();
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        env.update(2.0);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.now(), 5.0);
+        assert_eq!(env.windowed_throughput(ab, 2.5), 2.0);
+        assert_eq!(env.windowed_throughput(ab, 10.0), 3.0);
+        assert_eq!(env.windowed_throughput(ab, 0.5), 1.0);
+    }
+
+    #[test]
+    fn test_net_delivered() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 2.0, 1.0));
+        env.enable_event_log();
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.net_delivered(b), 2.0);
+        env.grab(bc).unwrap();
+        env.update(1.0);
+        assert_eq!(env.net_delivered(a), -2.0);
+        assert_eq!(env.net_delivered(b), 0.0);
+        assert_eq!(env.net_delivered(c), 2.0);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 2.0));
+        let ba = env.add_grabber(Grabber::new(b, a, 1.0, 1.0));
+        env.enable_event_log();
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        env.grab(ba).unwrap();
+        env.update(1.0);
+        env.update(1.0);
+        env.grab(ab).unwrap();
+        env.update(1.0);
+        assert_eq!(env.timeline(), vec![(ab, 0.0, 2.0), (ba, 1.0, 2.0)]);
+        env.update(1.0);
+        assert_eq!(env.timeline(), vec![(ab, 0.0, 2.0), (ba, 1.0, 2.0), (ab, 3.0, 5.0)]);
+    }
+
+    #[test]
+    fn test_starved_containers() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(2.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 4.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        env.enable_event_log();
+        env.grab(ab).unwrap();
+        for _ in 0..3 {
+            env.grab(bc).unwrap();
+            env.update(1.0);
+        }
+        assert_eq!(env.starved_containers(10.0), vec![b]);
+        assert_eq!(env.starved_containers(1.5), vec![]);
+        env.update(1.0);
+        env.grab(bc).unwrap();
+        assert!(env.starved_containers(10.0).is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+//! Parallel update of grabber timers, using `rayon`.
+
+use rayon::prelude::*;
+
+use crate::{advance, Advance, Environment, GrabberId, Transition};
+
+/// The minimum number of grabbers per task.
+///
+/// Splitting work costs more than advancing a few timers,
+/// so smaller environments are advanced serially.
+const MIN_GRABBERS_PER_TASK: usize = 256;
+
+impl Environment {
+    /// Updates the environment with a time delta,
+    /// advancing grabber timers in parallel.
+    ///
+    /// Timers are advanced independently on multiple threads,
+    /// after which deliveries are applied serially in grabber order.
+    /// Environments with few grabbers are advanced on the current thread.
+    /// The result is exactly the same as `update`.
+    ///
+    /// Panics if the time delta is negative or not finite.
+    pub fn update_parallel(&mut self, dt: f64) -> Vec<(GrabberId, Transition)> {
+        let busy = self.busy_mask();
+        self.step_with(dt, Environment::advance_timers_parallel);
+        self.transitions(&busy)
+    }
+
+    /// Advances the timers of all grabbers on multiple threads,
+    /// returning the advances in grabber order.
+    fn advance_timers_parallel(&mut self, dt: f64) -> Vec<(usize, Advance)> {
+        if self.grabbers.len() < 2 * MIN_GRABBERS_PER_TASK {return self.advance_timers(dt)}
+        self.grabbers.par_iter().zip(self.grabber_states.par_iter_mut()).enumerate()
+            .with_min_len(MIN_GRABBERS_PER_TASK)
+            .filter_map(|(i, (g, s))| advance(g, s, dt).map(|a| (i, a)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn fixture() -> Environment {
+        let mut env = Environment::new();
+        let n = 200;
+        for i in 0..n {
+            env.add_container(Container::new(i as f64).leak_rate(0.01));
+        }
+        for i in 0..1000 {
+            let source = ContainerId(i % n);
+            let target = ContainerId((i * 7 + 3) % n);
+            let delivery = if i % 3 == 0 {DeliveryMode::Continuous} else {DeliveryMode::Lump};
+            env.add_grabber(Grabber::new(source, target, 0.5, 0.25 + (i % 5) as f64 * 0.5)
+                .delivery(delivery)
+                .cooldown((i % 4) as f64 * 0.1));
+        }
+        env.delivery_cap = Some(5.0);
+        env
+    }
+
+    #[test]
+    fn test_update_parallel() {
+        let mut serial = fixture();
+        let mut parallel = fixture();
+        for k in 0..50 {
+            for i in 0..serial.grabbers.len() {
+                if (i + k) % 3 == 0 {
+                    let _ = serial.grab(GrabberId(i));
+                    let _ = parallel.grab(GrabberId(i));
+                }
+            }
+            assert_eq!(parallel.update_parallel(0.3), serial.update(0.3));
+            assert_eq!(parallel.to_bytes(), serial.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_update_parallel_small() {
+        let mut serial = Environment::new();
+        let a = serial.add_container(Container::new(3.0));
+        let b = serial.add_container(Container::new(0.0));
+        let ab = serial.add_grabber(Grabber::new(a, b, 1.0, 0.5).auto());
+        let mut parallel = serial.clone();
+        for _ in 0..4 {
+            assert_eq!(parallel.update_parallel(0.3), serial.update(0.3));
+        }
+        assert_eq!(parallel.to_bytes(), serial.to_bytes());
+        assert!(parallel.activation_count(ab) > 0);
+    }
+}
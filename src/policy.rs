@@ -0,0 +1,429 @@
+//! Control policies for the External Environment.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use crate::{ContainerId, EnvStats, Environment, GrabberId, TIME_EPSILON};
+
+/// Decides which grabbers to activate.
+pub trait Policy {
+    /// Selects grabbers to activate in the current state of the environment.
+    fn select(&self, env: &Environment) -> Vec<GrabberId>;
+}
+
+/// Activates all idle grabbers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GreedyPolicy;
+
+impl Policy for GreedyPolicy {
+    fn select(&self, env: &Environment) -> Vec<GrabberId> {
+        idle_grabbers(env).collect()
+    }
+}
+
+/// Activates one idle grabber at a time, cycling through the grabbers.
+#[derive(Clone, Debug, Default)]
+pub struct RoundRobinPolicy {
+    /// The grabber to consider first on the next selection.
+    next: Cell<usize>,
+}
+
+impl RoundRobinPolicy {
+    /// Creates a new round robin policy starting at the first grabber.
+    pub fn new() -> RoundRobinPolicy {
+        RoundRobinPolicy {next: Cell::new(0)}
+    }
+}
+
+impl Policy for RoundRobinPolicy {
+    fn select(&self, env: &Environment) -> Vec<GrabberId> {
+        let n = env.grabbers.len();
+        for k in 0..n {
+            let gid = GrabberId((self.next.get() + k) % n);
+            if !env.is_busy(gid) {
+                self.next.set((gid.0 + 1) % n);
+                return vec![gid];
+            }
+        }
+        vec![]
+    }
+}
+
+/// Activates idle grabbers whose source volume is above a threshold.
+#[derive(Clone, Copy, Debug)]
+pub struct ThresholdPolicy {
+    /// The volume the source must exceed.
+    pub threshold: f64,
+}
+
+impl Policy for ThresholdPolicy {
+    fn select(&self, env: &Environment) -> Vec<GrabberId> {
+        idle_grabbers(env)
+            .filter(|&gid| env.containers[env.grabbers[gid.0].source.0].volume > self.threshold)
+            .collect()
+    }
+}
+
+/// Iterates over the idle grabbers of an environment.
+fn idle_grabbers(env: &Environment) -> impl Iterator<Item = GrabberId> + '_ {
+    (0..env.grabbers.len()).map(GrabberId).filter(move |&gid| !env.is_busy(gid))
+}
+
+/// Reports what happened during a tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TickReport {
+    /// The grabbers that were activated.
+    pub activated: Vec<GrabberId>,
+    /// The grabbers that completed a transport.
+    pub completed: Vec<GrabberId>,
+}
+
+impl Environment {
+    /// Activates the grabbers selected by a policy, then updates with a time delta.
+    ///
+    /// Selected grabbers that are busy are ignored.
+    ///
+    /// Panics if the time delta is negative or not finite.
+    pub fn tick(&mut self, dt: f64, policy: &dyn Policy) -> TickReport {
+        let mut activated = vec![];
+        for gid in policy.select(self) {
+            if self.grab(gid).is_ok() {
+                activated.push(gid);
+            }
+        }
+        let completed = self.step(dt);
+        TickReport {activated, completed}
+    }
+
+    /// Runs the environment up to a time horizon, calling a function before each update.
+    ///
+    /// The function gets the environment and the current time,
+    /// so it can inspect, grab and modify before the environment is updated with the time delta.
+    /// This is like ticking with a policy, but the function has full control.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn run_with<F: FnMut(&mut Environment, f64)>(&mut self, horizon: f64, dt: f64, mut step_fn: F) {
+        self.run_steps(horizon, dt, |env| {
+            let now = env.now();
+            step_fn(env, now);
+            env.update(dt);
+        });
+    }
+
+    /// Calls a function repeatedly until the environment reaches a time horizon.
+    ///
+    /// The function must advance the environment by the time delta.
+    /// Panics if the time delta is not positive.
+    fn run_steps<F: FnMut(&mut Environment)>(&mut self, horizon: f64, dt: f64, mut step_fn: F) {
+        assert!(dt > 0.0, "Time delta must be positive");
+        let end = self.now() + horizon;
+        while self.now() + TIME_EPSILON < end {
+            step_fn(self);
+        }
+    }
+
+    /// Runs two policies on separate copies of the environment up to a time horizon,
+    /// returning the final stats of each run.
+    ///
+    /// Both runs start from the current state, ticking with the time delta.
+    /// The copies do not call the registered callbacks.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn compare_policies(
+        &self,
+        policy_a: &dyn Policy,
+        policy_b: &dyn Policy,
+        horizon: f64,
+        dt: f64
+    ) -> (EnvStats, EnvStats) {
+        let run = |policy: &dyn Policy| {
+            let mut env = self.clone();
+            env.run_steps(horizon, dt, |env| {env.tick(dt, policy);});
+            env.stats()
+        };
+        (run(policy_a), run(policy_b))
+    }
+
+    /// Estimates the time it takes a policy to move an amount of material from a source to a sink.
+    ///
+    /// Simulates a copy of the environment, ticking with the time delta,
+    /// and returns the elapsed time when the sink has gained the amount.
+    /// When the sink is a sink container, which discards material,
+    /// the volume sunk into it counts as gained.
+    /// Returns `None` if the sink can not be reached from the source,
+    /// or the amount has not accumulated within 10 000 ticks.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn time_to_deliver(
+        &self,
+        source: ContainerId,
+        sink: ContainerId,
+        amount: f64,
+        dt: f64,
+        policy: &dyn Policy
+    ) -> Option<f64> {
+        const MAX_TICKS: usize = 10_000;
+
+        assert!(dt > 0.0, "Time delta must be positive");
+        if !self.upstream_of(sink)[source.0] {return None}
+        let gained = |env: &Environment| env.volume_of_container(sink) +
+            env.sunk_volume(sink);
+        let mut env = self.clone();
+        let start = gained(&env);
+        for _ in 0..MAX_TICKS {
+            env.tick(dt, policy);
+            if gained(&env) - start >= amount {
+                return Some(env.now() - self.now());
+            }
+        }
+        None
+    }
+
+    /// Returns whether the environment is at equilibrium under a policy.
+    ///
+    /// Checks on a copy whether ticking once leaves the volume of every container
+    /// unchanged within `eps`, with no material left in flight.
+    /// The copy does not call the registered callbacks.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn is_fixed_point(&self, policy: &dyn Policy, dt: f64, eps: f64) -> bool {
+        assert!(dt > 0.0, "Time delta must be positive");
+        let mut env = self.clone();
+        env.tick(dt, policy);
+        env.in_flight_volume() <= eps &&
+            self.containers.iter().zip(env.containers.iter())
+                .all(|(before, after)| (after.volume - before.volume).abs() <= eps)
+    }
+
+    /// Estimates how the volume delivered over a time horizon under a policy
+    /// changes with the time of each grabber.
+    ///
+    /// The delivered volume is the volume gained by terminal containers plus the volume sunk.
+    /// Each derivative is a backward finite difference from re-simulating a copy
+    /// with the grabber time shortened by 10%,
+    /// since the simulation is not smooth in the grabber times.
+    /// A negative value means that speeding up the grabber delivers more.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn throughput_time_sensitivity(&self, horizon: f64, dt: f64, policy: &dyn Policy) -> Vec<f64> {
+        const RELATIVE_STEP: f64 = 0.1;
+
+        let terminals = self.terminal_containers();
+        let delivered = |env: &Environment| {
+            let mut env = env.clone();
+            let start: f64 = terminals.iter().map(|&c| env.volume_of_container(c)).sum::<f64>() +
+                env.total_sunk();
+            env.run_steps(horizon, dt, |env| {env.tick(dt, policy);});
+            terminals.iter().map(|&c| env.volume_of_container(c)).sum::<f64>() +
+                env.total_sunk() - start
+        };
+        let base = delivered(self);
+        (0..self.grabbers.len()).map(|i| {
+            let mut env = self.clone();
+            let h = env.grabbers[i].time * RELATIVE_STEP;
+            if h <= 0.0 {return 0.0}
+            env.grabbers[i].time -= h;
+            (base - delivered(&env)) / h
+        }).collect()
+    }
+
+    /// Applies a policy repeatedly until the environment reaches steady state,
+    /// or the number of iterations is exceeded.
+    ///
+    /// Steady state is detected when no container volume changes by more than
+    /// `1e-9` in a tick and no material is in flight.
+    /// Returns `true` if steady state was detected.
+    ///
+    /// Panics if the time delta is not positive.
+    pub fn warm_start(&mut self, policy: &dyn Policy, iterations: usize, dt: f64) -> bool {
+        const EPSILON: f64 = 1e-9;
+
+        assert!(dt > 0.0, "Time delta must be positive");
+
+        for _ in 0..iterations {
+            let before: Vec<f64> = self.containers.iter().map(|c| c.volume).collect();
+            self.tick(dt, policy);
+            let change = self.containers.iter().zip(before.iter())
+                .map(|(c, &v)| (c.volume - v).abs())
+                .fold(0.0, f64::max);
+            if change < EPSILON && self.in_flight_volume() < EPSILON {return true}
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Creates a chain `a -> b -> c` with an extra grabber `a -> c`.
+    fn fixture() -> (Environment, [GrabberId; 3]) {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(2.0));
+        let b = env.add_container(Container::new(0.5));
+        let c = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        let bc = env.add_grabber(Grabber::new(b, c, 1.0, 2.0));
+        let ac = env.add_grabber(Grabber::new(a, c, 1.0, 1.0));
+        (env, [ab, bc, ac])
+    }
+
+    #[test]
+    fn test_tick() {
+        let (mut env, [ab, bc, ac]) = fixture();
+        let report = env.tick(1.0, &GreedyPolicy);
+        assert_eq!(report.activated, vec![ab, bc, ac]);
+        assert_eq!(report.completed, vec![ab, ac]);
+        assert_eq!(env.volume_of_container(ContainerId(0)), 0.0);
+        let report = env.tick(1.0, &GreedyPolicy);
+        assert_eq!(report.activated, vec![ab, ac]);
+        assert_eq!(report.completed, vec![ab, bc, ac]);
+        assert_eq!(env.volume_of_container(ContainerId(1)), 1.0);
+        assert_eq!(env.volume_of_container(ContainerId(2)), 1.5);
+    }
+
+    #[test]
+    fn test_warm_start() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(4.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0)
+            .condition(Condition::Below(b, 2.0)));
+        assert!(!env.warm_start(&GreedyPolicy, 1, 0.5));
+        assert!(env.warm_start(&GreedyPolicy, 100, 0.5));
+        assert_eq!(env.volume_of_container(a), 2.0);
+        assert_eq!(env.volume_of_container(b), 2.0);
+    }
+
+    #[test]
+    fn test_greedy_policy() {
+        let (mut env, [ab, bc, ac]) = fixture();
+        assert_eq!(GreedyPolicy.select(&env), vec![ab, bc, ac]);
+        assert!(env.grab(bc).is_ok());
+        assert_eq!(GreedyPolicy.select(&env), vec![ab, ac]);
+    }
+
+    #[test]
+    fn test_round_robin_policy() {
+        let (mut env, [ab, bc, ac]) = fixture();
+        let policy = RoundRobinPolicy::new();
+        assert_eq!(policy.select(&env), vec![ab]);
+        assert_eq!(policy.select(&env), vec![bc]);
+        assert!(env.grab(ac).is_ok());
+        assert_eq!(policy.select(&env), vec![ab]);
+        assert!(env.grab(ab).is_ok());
+        assert!(env.grab(bc).is_ok());
+        assert_eq!(policy.select(&env), vec![]);
+    }
+
+    #[test]
+    fn test_threshold_policy() {
+        let (mut env, [ab, _, ac]) = fixture();
+        let policy = ThresholdPolicy {threshold: 1.0};
+        assert_eq!(policy.select(&env), vec![ab, ac]);
+        assert!(env.grab(ab).is_ok());
+        assert_eq!(policy.select(&env), vec![]);
+        assert_eq!(ThresholdPolicy {threshold: 0.0}.select(&env).len(), 2);
+    }
+
+    #[test]
+    fn test_compare_policies() {
+        let (env, _) = fixture();
+        let (greedy, threshold) = env.compare_policies(
+            &GreedyPolicy, &ThresholdPolicy {threshold: 8.0}, 4.0, 1.0);
+        assert_eq!(greedy.time, 4.0);
+        assert_eq!(threshold.time, 4.0);
+        assert_eq!(greedy.total_volume, threshold.total_volume);
+        // Greedy moves everything into the last container,
+        // while nothing exceeds the threshold.
+        assert_eq!(greedy.empty_containers, 2);
+        assert_eq!(threshold.empty_containers, 1);
+        assert_eq!(env.now(), 0.0);
+    }
+
+    #[test]
+    fn test_time_to_deliver() {
+        let (env, _) = fixture();
+        let (a, c) = (ContainerId(0), ContainerId(2));
+        assert_eq!(env.time_to_deliver(a, c, 1.0, 0.5, &GreedyPolicy), Some(1.0));
+        assert_eq!(env.time_to_deliver(a, c, 2.5, 0.5, &GreedyPolicy), Some(4.0));
+        assert_eq!(env.time_to_deliver(a, c, 5.0, 0.5, &GreedyPolicy), None);
+        assert_eq!(env.time_to_deliver(c, a, 1.0, 0.5, &GreedyPolicy), None);
+    }
+
+    #[test]
+    fn test_time_to_deliver_sink() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).sink());
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        assert_eq!(env.time_to_deliver(a, b, 3.0, 0.5, &GreedyPolicy), Some(3.0));
+    }
+
+    #[test]
+    fn test_time_to_deliver_other_sink() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).sink());
+        let c = env.add_container(Container::new(0.0).sink());
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        // Material sunk into `b` never reaches `c`.
+        env.add_grabber(Grabber::new(b, c, 1.0, 1.0));
+        assert_eq!(env.time_to_deliver(a, c, 3.0, 0.5, &GreedyPolicy), None);
+        assert_eq!(env.time_to_deliver(a, b, 3.0, 0.5, &GreedyPolicy), Some(3.0));
+    }
+
+    #[test]
+    fn test_throughput_time_sensitivity() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(100.0));
+        let b = env.add_container(Container::new(0.0));
+        let c = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.add_grabber(Grabber::new(b, c, 1.0, 2.0));
+        let sensitivity = env.throughput_time_sensitivity(20.0, 0.1, &GreedyPolicy);
+        assert!(sensitivity[1] < 0.0);
+        assert!(sensitivity[1].abs() > sensitivity[0].abs());
+    }
+
+    #[test]
+    fn test_run_with() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(0.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 5.0, 0.5));
+        for &at in &[1.0, 3.0, 5.0] {
+            env.schedule_inflow(a, 2.0, at);
+        }
+        let mut grabs = vec![];
+        env.run_with(6.0, 0.25, |env, now| {
+            if env.volume_of_container(a) >= 2.0 && env.grab(ab).is_ok() {
+                grabs.push(now);
+            }
+        });
+        assert_eq!(env.now(), 6.0);
+        assert_eq!(grabs, vec![1.0, 3.0, 5.0]);
+        assert_eq!(env.volume_of_container(a), 0.0);
+        assert_eq!(env.volume_of_container(b), 6.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Time delta must be positive")]
+    fn test_run_with_zero_dt() {
+        let mut env = Environment::new();
+        env.run_with(1.0, 0.0, |_, _| {});
+    }
+
+    #[test]
+    fn test_is_fixed_point() {
+        let (env, _) = fixture();
+        assert!(!env.is_fixed_point(&GreedyPolicy, 1.0, 1e-9));
+
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(0.0));
+        let b = env.add_container(Container::new(3.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        assert!(env.is_fixed_point(&GreedyPolicy, 1.0, 1e-9));
+    }
+}
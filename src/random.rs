@@ -0,0 +1,83 @@
+//! Generation of random environments, e.g. for fuzz testing.
+
+use crate::{Container, ContainerId, DeliveryMode, Environment, Grabber};
+use crate::rng::Rng;
+
+impl Environment {
+    /// Generates a random acyclic environment from a seed.
+    ///
+    /// Grabbers only move material from containers with lower ids to containers with higher ids.
+    /// The same seed always generates the same environment.
+    ///
+    /// Panics if there are grabbers but less than two containers.
+    pub fn random(n_containers: usize, n_grabbers: usize, seed: u64) -> Environment {
+        Environment::random_with(n_containers, n_grabbers, seed, false)
+    }
+
+    /// Generates a random environment from a seed, where grabbers may form cycles.
+    ///
+    /// The same seed always generates the same environment.
+    ///
+    /// Panics if there are grabbers but less than two containers.
+    pub fn random_cyclic(n_containers: usize, n_grabbers: usize, seed: u64) -> Environment {
+        Environment::random_with(n_containers, n_grabbers, seed, true)
+    }
+
+    /// Generates a random environment, optionally allowing cycles.
+    fn random_with(n_containers: usize, n_grabbers: usize, seed: u64, cyclic: bool) -> Environment {
+        assert!(n_grabbers == 0 || n_containers >= 2,
+                "Grabbers require at least two containers");
+        let mut rng = Rng::new(seed);
+        let mut range = |min: f64, max: f64| min + (max - min) * rng.next_f64();
+        let mut env = Environment::new();
+        for _ in 0..n_containers {
+            let volume = range(0.0, 10.0);
+            let mut c = Container::new(volume).height(range(0.0, 5.0));
+            if range(0.0, 1.0) < 0.5 {c = c.capacity(volume + range(0.0, 10.0))}
+            env.add_container(c);
+        }
+        for _ in 0..n_grabbers {
+            let n = n_containers as f64;
+            let (source, target) = loop {
+                let a = (range(0.0, n) as usize).min(n_containers - 1);
+                let b = (range(0.0, n) as usize).min(n_containers - 1);
+                if a == b {continue}
+                break if cyclic || a < b {(a, b)} else {(b, a)};
+            };
+            let delivery = if range(0.0, 1.0) < 0.5 {DeliveryMode::Lump} else {DeliveryMode::Continuous};
+            let g = Grabber::new(ContainerId(source), ContainerId(target),
+                                 range(0.5, 5.0), range(0.25, 2.25))
+                .delivery(delivery)
+                .cooldown(if range(0.0, 1.0) < 0.5 {0.0} else {range(0.0, 1.0)});
+            env.add_grabber(g);
+        }
+        env.set_seed(seed);
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_random() {
+        let env = Environment::random(8, 20, 7);
+        assert_eq!(env.num_containers(), 8);
+        assert_eq!(env.num_grabbers(), 20);
+        assert_eq!(env.validate(), Ok(()));
+        assert!(env.grabbers.iter().all(|g| g.source.0 < g.target.0));
+        assert_eq!(env.to_bytes(), Environment::random(8, 20, 7).to_bytes());
+        assert_ne!(env.to_bytes(), Environment::random(8, 20, 8).to_bytes());
+
+        let mut env = Environment::random_cyclic(5, 30, 3);
+        assert_eq!(env.validate(), Ok(()));
+        for i in 0..30 {
+            let _ = env.grab(GrabberId(i));
+        }
+        for _ in 0..10 {
+            env.update(0.5);
+            assert_eq!(env.validate(), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Seeded pseudo-random number generation.
+
+/// A small deterministic random number generator (SplitMix64).
+///
+/// The same seed always produces the same sequence,
+/// which keeps stochastic simulations reproducible.
+#[derive(Clone, Debug)]
+pub(crate) struct Rng {
+    /// The internal state.
+    pub(crate) state: u64,
+}
+
+impl Rng {
+    /// Creates a new generator from a seed.
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng {state: seed}
+    }
+
+    /// Creates a generator for sensor noise from a seed,
+    /// producing a different sequence than `Rng::new` with the same seed.
+    pub(crate) fn sensor(seed: u64) -> Rng {
+        Rng {state: seed ^ 0x5e45_5012_c0ff_ee00}
+    }
+
+    /// Generates the next 64 random bits.
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Generates a number uniformly in the range `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Generates a number from the standard normal distribution,
+    /// using the polar method of Marsaglia.
+    pub(crate) fn next_gaussian(&mut self) -> f64 {
+        loop {
+            let u = 2.0 * self.next_f64() - 1.0;
+            let v = 2.0 * self.next_f64() - 1.0;
+            let s = u * u + v * v;
+            if s > 0.0 && s < 1.0 {
+                return u * crate::sqrt(-2.0 * ln(s) / s);
+            }
+        }
+    }
+}
+
+/// Computes the natural logarithm of a positive finite value.
+///
+/// `f64::ln` requires the standard library.
+fn ln(x: f64) -> f64 {
+    const LN_2: f64 = core::f64::consts::LN_2;
+
+    // Split into `m * 2^e` with `m` in `[1, 2)`, handling subnormal values.
+    let (x, offset) = if x < f64::MIN_POSITIVE {(x * (1u64 << 54) as f64, -54)} else {(x, 0)};
+    let bits = x.to_bits();
+    let e = ((bits >> 52) & 0x7ff) as i64 - 1023 + offset;
+    let m = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | (1023 << 52));
+    // `ln(m) = 2 * atanh(t)` with `t = (m - 1) / (m + 1)` in `[0, 1/3)`.
+    let t = (m - 1.0) / (m + 1.0);
+    let t2 = t * t;
+    let mut term = t;
+    let mut sum: f64 = 0.0;
+    let mut k = 1.0;
+    while term.abs() > 1e-17 * sum.abs() + f64::MIN_POSITIVE {
+        sum += term / k;
+        term *= t2;
+        k += 2.0;
+    }
+    2.0 * sum + e as f64 * LN_2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        for _ in 0..10 {
+            let x = a.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(x, b.next_f64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    #[test]
+    fn test_ln() {
+        for &x in &[1.0, 2.0, 0.5, 10.0, 1e-300, 1e300, 5e-324] {
+            assert!((ln(x) - x.ln()).abs() <= 1e-14 * x.ln().abs().max(1.0), "ln({})", x);
+        }
+        assert!((ln(core::f64::consts::E) - 1.0).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_gaussian() {
+        let mut rng = Rng::new(3);
+        let n = 10_000;
+        let samples: std::vec::Vec<f64> = (0..n).map(|_| rng.next_gaussian()).collect();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let variance = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.05);
+        assert!((variance - 1.0).abs() < 0.05);
+    }
+}
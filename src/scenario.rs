@@ -0,0 +1,867 @@
+//! Versioned JSON format for scenario files.
+//!
+//! A scenario describes the model of an environment and the initial volumes,
+//! such that external tools can produce and consume environments
+//! independently of the internal layout.
+//! The simulation state, statistics and instrumentation are not included,
+//! see the binary format for a complete snapshot.
+//!
+//! A scenario is a JSON object with the following fields:
+//!
+//! - `version` (required): The scenario version, see `SCENARIO_VERSION`.
+//! - `containers` (required): A list of containers, each an object with
+//!   `volume` (required), `sink`, `fixed`, `height`, `position` (a pair of numbers),
+//!   `leak_rate`, `capacity`, `unit` and `unit_scale`.
+//!   The capacity is omitted when unlimited.
+//! - `grabbers` (required): A list of grabbers, each an object with
+//!   `source`, `target`, `volume` and `time` (required),
+//!   `delivery` (`"lump"` or `"continuous"`), `jitter`, `cooldown`,
+//!   `condition` (an object with `kind` being `"below"` or `"above"`, `container` and `level`),
+//!   `batch_size`, `require_space`, `auto`, `reaction_rate`, `wear_factor`, `min_volume`,
+//!   `stages`, `ramp` and `demand_threshold` (an object with `container` and `level`).
+//! - `names`: A list of objects with `name` and `container`.
+//! - `links`: A list of groups of grabbers that activate together.
+//! - `shared_capacity`: A list of groups of grabbers that share capacity.
+//! - `allowed_transfers`: A list of `[source, target]` pairs, if transfers are restricted.
+//! - `saturation`, `gravity` and `delivery_cap`.
+//!   The saturation and delivery cap are omitted when unlimited.
+//!
+//! Containers and grabbers are referred to by their index in the lists.
+//! Omitted optional fields get the same defaults as the builders.
+//! Numbers must be finite, since JSON can not represent other numbers.
+//! Unknown fields are rejected.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::{
+    Condition,
+    Container,
+    ContainerId,
+    DeliveryMode,
+    Environment,
+    Grabber,
+    GrabberId,
+    ValidationError,
+};
+
+/// The version of the scenario format.
+///
+/// Bumped whenever the schema changes after a release.
+/// Scenarios of older versions are read too,
+/// so a bump may only add optional fields with defaults.
+pub const SCENARIO_VERSION: u64 = 1;
+
+/// Describes an error when reading a scenario.
+///
+/// Fields are referred to by their path, e.g. `grabbers[0].time`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScenarioError {
+    /// The text is not valid JSON, at a byte offset.
+    Syntax(usize),
+    /// The scenario was written with an unsupported version.
+    UnsupportedVersion(f64),
+    /// A required field is missing.
+    MissingField(String),
+    /// A field is not part of the schema.
+    UnknownField(String),
+    /// A field occurs more than once in an object.
+    DuplicateField(String),
+    /// A field has a value of the wrong type or out of range.
+    InvalidValue(String),
+    /// The described environment is not well-formed.
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Syntax(pos) => write!(f, "Invalid JSON at byte {}", pos),
+            ScenarioError::UnsupportedVersion(v) =>
+                write!(f, "Unsupported scenario version {}", v),
+            ScenarioError::MissingField(path) => write!(f, "Missing field `{}`", path),
+            ScenarioError::UnknownField(path) => write!(f, "Unknown field `{}`", path),
+            ScenarioError::DuplicateField(path) => write!(f, "Duplicate field `{}`", path),
+            ScenarioError::InvalidValue(path) => write!(f, "Invalid value for `{}`", path),
+            ScenarioError::Invalid(err) => write!(f, "Invalid scenario: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ScenarioError {}
+
+/// Stores a parsed JSON value.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Fields in the order they appear.
+    Object(Vec<(String, Json)>),
+}
+
+/// The maximum nesting depth of arrays and objects,
+/// which keeps deeply nested input from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Parses JSON text.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// The number of arrays and objects being parsed.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self) -> Result<T, ScenarioError> {Err(ScenarioError::Syntax(self.pos))}
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.data.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.data.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), ScenarioError> {
+        if self.peek() != Some(b) {return self.error()}
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, text: &str, value: Json) -> Result<Json, ScenarioError> {
+        if !self.data[self.pos..].starts_with(text.as_bytes()) {return self.error()}
+        self.pos += text.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, ScenarioError> {
+        match self.peek() {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => self.nested(Parser::array),
+            Some(b'{') => self.nested(Parser::object),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => self.error(),
+        }
+    }
+
+    /// Parses an array or object, failing when nested too deeply.
+    fn nested(
+        &mut self,
+        f: fn(&mut Parser<'a>) -> Result<Json, ScenarioError>
+    ) -> Result<Json, ScenarioError> {
+        if self.depth >= MAX_DEPTH {return self.error()}
+        self.depth += 1;
+        let value = f(self)?;
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Json, ScenarioError> {
+        self.pos += 1;
+        let mut items = vec![];
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return self.error(),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, ScenarioError> {
+        self.pos += 1;
+        let mut fields = vec![];
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            if self.peek() != Some(b'"') {return self.error()}
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return self.error(),
+            }
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.data.get(self.pos) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Result<Json, ScenarioError> {
+        let start = self.pos;
+        if self.data[self.pos] == b'-' {self.pos += 1}
+        match self.data.get(self.pos) {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {self.digits();}
+            _ => return self.error(),
+        }
+        if self.data.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {return self.error()}
+        }
+        if let Some(b'e' | b'E') = self.data.get(self.pos) {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.data.get(self.pos) {self.pos += 1}
+            if self.digits() == 0 {return self.error()}
+        }
+        // The scanned bytes are ASCII.
+        let text = core::str::from_utf8(&self.data[start..self.pos]).unwrap();
+        match text.parse() {
+            Ok(v) => Ok(Json::Number(v)),
+            Err(_) => Err(ScenarioError::Syntax(start)),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ScenarioError> {
+        let digits = match self.data.get(self.pos..self.pos + 4) {
+            Some(digits) => digits,
+            None => return self.error(),
+        };
+        let mut v = 0;
+        for &d in digits {
+            let digit = match (d as char).to_digit(16) {
+                Some(digit) => digit,
+                None => return self.error(),
+            };
+            v = v * 16 + digit;
+        }
+        self.pos += 4;
+        Ok(v)
+    }
+
+    fn string(&mut self) -> Result<String, ScenarioError> {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(&b) = self.data.get(self.pos) {
+                if b == b'"' || b == b'\\' || b < 0x20 {break}
+                self.pos += 1;
+            }
+            match core::str::from_utf8(&self.data[start..self.pos]) {
+                Ok(text) => s.push_str(text),
+                Err(err) => return Err(ScenarioError::Syntax(start + err.valid_up_to())),
+            }
+            match self.data.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => self.pos += 1,
+                _ => return self.error(),
+            }
+            let escape = match self.data.get(self.pos) {
+                Some(&escape) => escape,
+                None => return self.error(),
+            };
+            self.pos += 1;
+            match escape {
+                b'"' => s.push('"'),
+                b'\\' => s.push('\\'),
+                b'/' => s.push('/'),
+                b'b' => s.push('\u{8}'),
+                b'f' => s.push('\u{c}'),
+                b'n' => s.push('\n'),
+                b'r' => s.push('\r'),
+                b't' => s.push('\t'),
+                b'u' => {
+                    let mut code = self.hex4()?;
+                    if (0xD800..0xDC00).contains(&code) {
+                        if !self.data[self.pos..].starts_with(b"\\u") {return self.error()}
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        if !(0xDC00..0xE000).contains(&low) {return self.error()}
+                        code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                    }
+                    match char::from_u32(code) {
+                        Some(c) => s.push(c),
+                        None => return self.error(),
+                    }
+                }
+                _ => return self.error(),
+            }
+        }
+    }
+}
+
+/// Parses JSON text into a value.
+fn parse(text: &str) -> Result<Json, ScenarioError> {
+    let mut p = Parser {data: text.as_bytes(), pos: 0, depth: 0};
+    let value = p.value()?;
+    if p.peek().is_some() {return p.error()}
+    Ok(value)
+}
+
+/// Checks that all numbers in a JSON value are finite,
+/// returning an error with the path of the first number that is not.
+fn check_finite(value: &Json, path: String) -> Result<(), ScenarioError> {
+    match value {
+        Json::Number(v) if !v.is_finite() => Err(ScenarioError::InvalidValue(path)),
+        Json::Array(items) => items.iter().enumerate()
+            .try_for_each(|(i, item)| check_finite(item, format!("{}[{}]", path, i))),
+        Json::Object(fields) => fields.iter().try_for_each(|(key, item)| {
+            let path = if path.is_empty() {key.clone()} else {format!("{}.{}", path, key)};
+            check_finite(item, path)
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Writes a JSON value, indenting nested objects and arrays.
+fn write(value: &Json, indent: usize, out: &mut String) {
+    const INDENT: &str = "  ";
+
+    match value {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(if *b {"true"} else {"false"}),
+        Json::Number(v) => out.push_str(&format!("{}", v)),
+        Json::String(s) => write_string(s, out),
+        Json::Array(items) if items.iter().all(|item| matches!(item, Json::Number(_))) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {out.push_str(", ")}
+                write(item, indent, out);
+            }
+            out.push(']');
+        }
+        Json::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(if i > 0 {",\n"} else {"\n"});
+                for _ in 0..=indent {out.push_str(INDENT)}
+                write(item, indent + 1, out);
+            }
+            if !items.is_empty() {
+                out.push('\n');
+                for _ in 0..indent {out.push_str(INDENT)}
+            }
+            out.push(']');
+        }
+        Json::Object(fields) => {
+            out.push('{');
+            for (i, (key, value)) in fields.iter().enumerate() {
+                out.push_str(if i > 0 {",\n"} else {"\n"});
+                for _ in 0..=indent {out.push_str(INDENT)}
+                write_string(key, out);
+                out.push_str(": ");
+                write(value, indent + 1, out);
+            }
+            if !fields.is_empty() {
+                out.push('\n');
+                for _ in 0..indent {out.push_str(INDENT)}
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Writes a JSON string with escapes.
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Joins a field to a path.
+fn field_path(path: &str, key: &str) -> String {
+    if path.is_empty() {key.into()} else {format!("{}.{}", path, key)}
+}
+
+/// Reads the fields of an object, rejecting unknown and duplicate fields.
+struct Fields<'a> {
+    path: String,
+    fields: &'a [(String, Json)],
+}
+
+impl<'a> Fields<'a> {
+    fn new(value: &'a Json, path: String, known: &[&str]) -> Result<Fields<'a>, ScenarioError> {
+        let fields = match value {
+            Json::Object(fields) => fields,
+            _ => return Err(ScenarioError::InvalidValue(path)),
+        };
+        for (i, (key, _)) in fields.iter().enumerate() {
+            if !known.contains(&key.as_str()) {
+                return Err(ScenarioError::UnknownField(field_path(&path, key)));
+            }
+            if fields[..i].iter().any(|(k, _)| k == key) {
+                return Err(ScenarioError::DuplicateField(field_path(&path, key)));
+            }
+        }
+        Ok(Fields {path, fields})
+    }
+
+    fn path(&self, key: &str) -> String {field_path(&self.path, key)}
+
+    fn get(&self, key: &str) -> Option<&'a Json> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn required(&self, key: &str) -> Result<&'a Json, ScenarioError> {
+        self.get(key).ok_or_else(|| ScenarioError::MissingField(self.path(key)))
+    }
+
+    fn invalid<T>(&self, key: &str) -> Result<T, ScenarioError> {
+        Err(ScenarioError::InvalidValue(self.path(key)))
+    }
+
+    fn number(&self, key: &str) -> Result<f64, ScenarioError> {
+        match self.required(key)? {
+            Json::Number(v) => Ok(*v),
+            _ => self.invalid(key),
+        }
+    }
+
+    fn number_or(&self, key: &str, default: f64) -> Result<f64, ScenarioError> {
+        if self.get(key).is_none() {return Ok(default)}
+        self.number(key)
+    }
+
+    fn option_number(&self, key: &str) -> Result<Option<f64>, ScenarioError> {
+        if self.get(key).is_none() {return Ok(None)}
+        self.number(key).map(Some)
+    }
+
+    fn bool_or(&self, key: &str, default: bool) -> Result<bool, ScenarioError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(Json::Bool(b)) => Ok(*b),
+            Some(_) => self.invalid(key),
+        }
+    }
+
+    /// Reads a non-negative integer below a limit.
+    fn index(&self, key: &str, limit: usize) -> Result<usize, ScenarioError> {
+        let v = self.number(key)?;
+        index(v, limit).ok_or_else(|| ScenarioError::InvalidValue(self.path(key)))
+    }
+
+    fn array(&self, key: &str) -> Result<&'a [Json], ScenarioError> {
+        match self.get(key) {
+            None => Ok(&[]),
+            Some(Json::Array(items)) => Ok(items),
+            Some(_) => self.invalid(key),
+        }
+    }
+}
+
+/// Converts a number to a non-negative integer below a limit.
+fn index(v: f64, limit: usize) -> Option<usize> {
+    if v >= 0.0 && v < limit as f64 && v as usize as f64 == v {Some(v as usize)} else {None}
+}
+
+/// Reads a group of grabbers.
+fn grabber_group(value: &Json, path: String, m: usize) -> Result<Vec<GrabberId>, ScenarioError> {
+    let items = match value {
+        Json::Array(items) => items,
+        _ => return Err(ScenarioError::InvalidValue(path)),
+    };
+    items.iter().enumerate().map(|(i, item)| match item {
+        Json::Number(v) => index(*v, m).map(GrabberId),
+        _ => None,
+    }.ok_or_else(|| ScenarioError::InvalidValue(format!("{}[{}]", path, i)))).collect()
+}
+
+impl Environment {
+    /// Writes the model and initial volumes of the environment as a JSON scenario.
+    ///
+    /// See the module documentation of the scenario format for the schema.
+    /// Returns `ScenarioError::InvalidValue` with the path of the first number that is not finite,
+    /// since it could not be read back.
+    pub fn to_scenario_json(&self) -> Result<String, ScenarioError> {
+        fn num(v: f64) -> Json {Json::Number(v)}
+        fn id(i: usize) -> Json {Json::Number(i as f64)}
+        fn group(gids: &[GrabberId]) -> Json {Json::Array(gids.iter().map(|g| id(g.0)).collect())}
+        fn field(key: &str, value: Json) -> (String, Json) {(key.into(), value)}
+
+        let containers = self.containers.iter().map(|c| {
+            let mut fields = vec![
+                field("volume", num(c.volume)),
+                field("sink", Json::Bool(c.sink)),
+                field("fixed", Json::Bool(c.fixed)),
+                field("height", num(c.height)),
+                field("position", Json::Array(vec![num(c.position[0]), num(c.position[1])])),
+                field("leak_rate", num(c.leak_rate)),
+            ];
+            if c.capacity != f64::INFINITY {fields.push(field("capacity", num(c.capacity)))}
+            if let Some(unit) = &c.unit {fields.push(field("unit", Json::String(unit.clone())))}
+            fields.push(field("unit_scale", num(c.unit_scale)));
+            Json::Object(fields)
+        }).collect();
+        let grabbers = self.grabbers.iter().map(|g| {
+            let mut fields = vec![
+                field("source", id(g.source.0)),
+                field("target", id(g.target.0)),
+                field("volume", num(g.volume)),
+                field("time", num(g.time)),
+                field("delivery", Json::String(match g.delivery {
+                    DeliveryMode::Lump => "lump".into(),
+                    DeliveryMode::Continuous => "continuous".into(),
+                })),
+                field("jitter", num(g.jitter)),
+                field("cooldown", num(g.cooldown)),
+            ];
+            if let Some(cond) = g.condition {
+                let (kind, c, level) = match cond {
+                    Condition::Below(c, level) => ("below", c, level),
+                    Condition::Above(c, level) => ("above", c, level),
+                };
+                fields.push(field("condition", Json::Object(vec![
+                    field("kind", Json::String(kind.into())),
+                    field("container", id(c.0)),
+                    field("level", num(level)),
+                ])));
+            }
+            if let Some(b) = g.batch_size {fields.push(field("batch_size", num(b)))}
+            fields.extend(vec![
+                field("require_space", Json::Bool(g.require_space)),
+                field("auto", Json::Bool(g.auto)),
+                field("reaction_rate", num(g.reaction_rate)),
+                field("wear_factor", num(g.wear_factor)),
+                field("min_volume", num(g.min_volume)),
+                field("stages", id(g.stages)),
+                field("ramp", num(g.ramp)),
+            ]);
+            if let Some((c, level)) = g.demand_threshold {
+                fields.push(field("demand_threshold", Json::Object(vec![
+                    field("container", id(c.0)),
+                    field("level", num(level)),
+                ])));
+            }
+            Json::Object(fields)
+        }).collect();
+        let names = self.names.iter().map(|(name, c)| Json::Object(vec![
+            field("name", Json::String(name.clone())),
+            field("container", id(c.0)),
+        ])).collect();
+
+        let mut fields = vec![
+            field("version", num(SCENARIO_VERSION as f64)),
+            field("containers", Json::Array(containers)),
+            field("grabbers", Json::Array(grabbers)),
+            field("names", Json::Array(names)),
+            field("links", Json::Array(self.links.iter().map(|g| group(g)).collect())),
+            field("shared_capacity", Json::Array(self.shared.iter().map(|g| group(g)).collect())),
+        ];
+        if let Some(allowed) = &self.allowed_transfers {
+            let pairs = allowed.iter()
+                .map(|&(source, target)| Json::Array(vec![id(source.0), id(target.0)]))
+                .collect();
+            fields.push(field("allowed_transfers", Json::Array(pairs)));
+        }
+        // An infinite limit is the same as no limit.
+        if let Some(v) = self.saturation.filter(|&v| v != f64::INFINITY) {
+            fields.push(field("saturation", num(v)));
+        }
+        fields.push(field("gravity", num(self.gravity)));
+        if let Some(v) = self.delivery_cap.filter(|&v| v != f64::INFINITY) {
+            fields.push(field("delivery_cap", num(v)));
+        }
+
+        let root = Json::Object(fields);
+        check_finite(&root, String::new())?;
+        let mut out = String::new();
+        write(&root, 0, &mut out);
+        out.push('\n');
+        Ok(out)
+    }
+
+    /// Reads an environment from a JSON scenario.
+    ///
+    /// See the module documentation of the scenario format for the schema.
+    /// The environment is validated after reading.
+    pub fn from_scenario_json(text: &str) -> Result<Environment, ScenarioError> {
+        let root = parse(text)?;
+        let root = Fields::new(&root, String::new(), &[
+            "version", "containers", "grabbers", "names", "links", "shared_capacity",
+            "allowed_transfers", "saturation", "gravity", "delivery_cap",
+        ])?;
+        let version = root.number("version")?;
+        if !(1.0..=SCENARIO_VERSION as f64).contains(&version) || version as u64 as f64 != version {
+            return Err(ScenarioError::UnsupportedVersion(version));
+        }
+
+        let mut env = Environment::new();
+        root.required("containers")?;
+        let containers = root.array("containers")?;
+        let n = containers.len();
+        for (i, value) in containers.iter().enumerate() {
+            let fields = Fields::new(value, format!("containers[{}]", i), &[
+                "volume", "sink", "fixed", "height", "position", "leak_rate", "capacity", "unit",
+                "unit_scale",
+            ])?;
+            let mut c = Container::new(fields.number("volume")?);
+            c.sink = fields.bool_or("sink", false)?;
+            c.fixed = fields.bool_or("fixed", false)?;
+            c.height = fields.number_or("height", 0.0)?;
+            c.position = match fields.get("position") {
+                None => [0.0, 0.0],
+                Some(Json::Array(xy)) => match xy[..] {
+                    [Json::Number(x), Json::Number(y)] => [x, y],
+                    _ => return fields.invalid("position"),
+                },
+                Some(_) => return fields.invalid("position"),
+            };
+            c.leak_rate = fields.number_or("leak_rate", 0.0)?;
+            c.capacity = fields.number_or("capacity", f64::INFINITY)?;
+            c.unit = match fields.get("unit") {
+                None => None,
+                Some(Json::String(unit)) => Some(unit.clone()),
+                Some(_) => return fields.invalid("unit"),
+            };
+            c.unit_scale = fields.number_or("unit_scale", 1.0)?;
+            env.add_container(c);
+        }
+
+        root.required("grabbers")?;
+        let grabbers = root.array("grabbers")?;
+        let m = grabbers.len();
+        for (i, value) in grabbers.iter().enumerate() {
+            let fields = Fields::new(value, format!("grabbers[{}]", i), &[
+                "source", "target", "volume", "time", "delivery", "jitter", "cooldown",
+                "condition", "batch_size", "require_space", "auto", "reaction_rate",
+                "wear_factor", "min_volume", "stages", "ramp", "demand_threshold",
+            ])?;
+            let source = ContainerId(fields.index("source", n)?);
+            let target = ContainerId(fields.index("target", n)?);
+            let mut g = Grabber::new(source, target, fields.number("volume")?, fields.number("time")?);
+            g.delivery = match fields.get("delivery") {
+                None => DeliveryMode::Lump,
+                Some(Json::String(s)) if s == "lump" => DeliveryMode::Lump,
+                Some(Json::String(s)) if s == "continuous" => DeliveryMode::Continuous,
+                Some(_) => return fields.invalid("delivery"),
+            };
+            g.jitter = fields.number_or("jitter", 0.0)?;
+            g.cooldown = fields.number_or("cooldown", 0.0)?;
+            if let Some(value) = fields.get("condition") {
+                let cond = Fields::new(value, fields.path("condition"), &["kind", "container", "level"])?;
+                let c = ContainerId(cond.index("container", n)?);
+                let level = cond.number("level")?;
+                g.condition = Some(match cond.required("kind")? {
+                    Json::String(s) if s == "below" => Condition::Below(c, level),
+                    Json::String(s) if s == "above" => Condition::Above(c, level),
+                    _ => return cond.invalid("kind"),
+                });
+            }
+            g.batch_size = fields.option_number("batch_size")?;
+            g.require_space = fields.bool_or("require_space", false)?;
+            g.auto = fields.bool_or("auto", false)?;
+            g.reaction_rate = fields.number_or("reaction_rate", 0.0)?;
+            g.wear_factor = fields.number_or("wear_factor", 1.0)?;
+            g.min_volume = fields.number_or("min_volume", 0.0)?;
+            g.stages = match fields.get("stages") {
+                None => 1,
+                Some(_) => fields.index("stages", usize::MAX)?,
+            };
+            g.ramp = fields.number_or("ramp", 1.0)?;
+            if let Some(value) = fields.get("demand_threshold") {
+                let demand = Fields::new(value, fields.path("demand_threshold"),
+                                         &["container", "level"])?;
+                let c = ContainerId(demand.index("container", n)?);
+                g.demand_threshold = Some((c, demand.number("level")?));
+            }
+            env.add_grabber(g);
+        }
+
+        // Grabbers added before restricting transfers stay valid.
+        if let Some(value) = root.get("allowed_transfers") {
+            let pairs = match value {
+                Json::Array(pairs) => pairs,
+                _ => return root.invalid("allowed_transfers"),
+            };
+            for (i, pair) in pairs.iter().enumerate() {
+                let ids: Option<Vec<usize>> = match pair {
+                    Json::Array(ids) if ids.len() == 2 => ids.iter().map(|v| match v {
+                        Json::Number(v) => index(*v, n),
+                        _ => None,
+                    }).collect(),
+                    _ => None,
+                };
+                match ids {
+                    Some(ids) => env.allow_transfer(ContainerId(ids[0]), ContainerId(ids[1])),
+                    None => return Err(ScenarioError::InvalidValue(
+                        format!("allowed_transfers[{}]", i))),
+                }
+            }
+        }
+
+        for (i, value) in root.array("names")?.iter().enumerate() {
+            let fields = Fields::new(value, format!("names[{}]", i), &["name", "container"])?;
+            let c = ContainerId(fields.index("container", n)?);
+            let name = match fields.required("name")? {
+                Json::String(name) => name,
+                _ => return fields.invalid("name"),
+            };
+            if env.alias_container(c, name).is_err() {return fields.invalid("name")}
+        }
+        for (i, value) in root.array("links")?.iter().enumerate() {
+            env.link_grabbers(&grabber_group(value, format!("links[{}]", i), m)?);
+        }
+        for (i, value) in root.array("shared_capacity")?.iter().enumerate() {
+            env.share_capacity(&grabber_group(value, format!("shared_capacity[{}]", i), m)?);
+        }
+
+        env.saturation = root.option_number("saturation")?;
+        env.gravity = root.number_or("gravity", env.gravity)?;
+        env.delivery_cap = root.option_number("delivery_cap")?;
+        env.validate().map_err(ScenarioError::Invalid)?;
+        Ok(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const SCENARIO: &str = r#"{
+        "version": 1,
+        "containers": [
+            {"volume": 10, "height": 2.5, "fixed": true},
+            {"volume": 0, "position": [1, -2.5], "capacity": 8, "unit": "L \"liquid\""},
+            {"volume": 0, "sink": true}
+        ],
+        "grabbers": [
+            {"source": 0, "target": 1, "volume": 2, "time": 1,
+             "condition": {"kind": "above", "container": 0, "level": 1},
+             "auto": true, "demand_threshold": {"container": 1, "level": 4}},
+            {"source": 1, "target": 2, "volume": 1, "time": 0.5, "delivery": "continuous",
+             "stages": 2, "batch_size": 0.5}
+        ],
+        "names": [{"name": "tank", "container": 1}],
+        "links": [[0, 1]],
+        "gravity": 10
+    }"#;
+
+    #[test]
+    fn test_scenario() {
+        let env = Environment::from_scenario_json(SCENARIO).unwrap();
+        assert_eq!(env.containers.len(), 3);
+        assert_eq!(env.containers[0].height, 2.5);
+        assert!(env.containers[0].fixed);
+        assert!(!env.containers[1].fixed);
+        assert_eq!(env.containers[1].position, [1.0, -2.5]);
+        assert_eq!(env.containers[1].capacity, 8.0);
+        assert_eq!(env.containers[1].unit.as_deref(), Some("L \"liquid\""));
+        assert!(env.containers[2].sink);
+        assert_eq!(env.grabbers[0].condition, Some(Condition::Above(ContainerId(0), 1.0)));
+        assert_eq!(env.grabbers[0].demand_threshold, Some((ContainerId(1), 4.0)));
+        assert_eq!(env.grabbers[1].delivery, DeliveryMode::Continuous);
+        assert_eq!(env.grabbers[1].stages, 2);
+        assert_eq!(env.grabbers[1].batch_size, Some(0.5));
+        assert_eq!(env.grabbers[1].wear_factor, 1.0);
+        assert_eq!(env.container_by_name("tank"), Some(ContainerId(1)));
+        assert_eq!(env.linked_grabbers(GrabberId(0)), vec![GrabberId(0), GrabberId(1)]);
+        assert_eq!(env.gravity, 10.0);
+        assert_eq!(env.saturation, None);
+
+        let json = env.to_scenario_json().unwrap();
+        let env2 = Environment::from_scenario_json(&json).unwrap();
+        assert_eq!(env2.to_scenario_json().unwrap(), json);
+        assert_eq!(env2.to_bytes(), env.to_bytes());
+
+        // Infinite limits are omitted, other non-finite numbers can not be written.
+        let mut unlimited = env.clone();
+        unlimited.saturation = Some(f64::INFINITY);
+        unlimited.delivery_cap = Some(f64::INFINITY);
+        assert_eq!(unlimited.to_scenario_json().unwrap(), json);
+        let mut infinite = env.clone();
+        infinite.gravity = f64::INFINITY;
+        assert_eq!(infinite.to_scenario_json(), Err(ScenarioError::InvalidValue("gravity".into())));
+        infinite.gravity = 10.0;
+        infinite.containers[1].height = f64::NAN;
+        assert_eq!(infinite.to_scenario_json(),
+                   Err(ScenarioError::InvalidValue("containers[1].height".into())));
+    }
+
+    #[test]
+    fn test_scenario_allow_list() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0));
+        env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.allow_transfer(b, a);
+        let json = env.to_scenario_json().unwrap();
+        let env2 = Environment::from_scenario_json(&json).unwrap();
+        assert_eq!(env2.to_scenario_json().unwrap(), json);
+        assert!(!env2.is_transfer_allowed(a, b));
+    }
+
+    #[test]
+    fn test_malformed_scenario() {
+        let missing = SCENARIO.replace(r#""volume": 1, "time": 0.5,"#, r#""volume": 1,"#);
+        let err = Environment::from_scenario_json(&missing).err().unwrap();
+        assert_eq!(err, ScenarioError::MissingField("grabbers[1].time".into()));
+        assert_eq!(format!("{}", err), "Missing field `grabbers[1].time`");
+
+        let unknown = SCENARIO.replace(r#""height": 2.5"#, r#""hieght": 2.5"#);
+        assert_eq!(Environment::from_scenario_json(&unknown).err(),
+                   Some(ScenarioError::UnknownField("containers[0].hieght".into())));
+        let invalid = SCENARIO.replace(r#""target": 2"#, r#""target": 3"#);
+        assert_eq!(Environment::from_scenario_json(&invalid).err(),
+                   Some(ScenarioError::InvalidValue("grabbers[1].target".into())));
+        let version = SCENARIO.replace(r#""version": 1"#, r#""version": 2"#);
+        assert_eq!(Environment::from_scenario_json(&version).err(),
+                   Some(ScenarioError::UnsupportedVersion(2.0)));
+        let negative = SCENARIO.replace(r#""volume": 10"#, r#""volume": -10"#);
+        assert_eq!(Environment::from_scenario_json(&negative).err(),
+                   Some(ScenarioError::Invalid(ValidationError::InvalidVolume(ContainerId(0)))));
+        let leaking = SCENARIO.replace(r#""height": 2.5"#, r#""height": 2.5, "leak_rate": -1"#);
+        assert_eq!(Environment::from_scenario_json(&leaking).err(),
+                   Some(ScenarioError::Invalid(ValidationError::InvalidLeakRate(ContainerId(0)))));
+        assert_eq!(Environment::from_scenario_json(r#"{"version": 1,}"#).err(),
+                   Some(ScenarioError::Syntax(14)));
+    }
+
+    #[test]
+    fn test_deeply_nested_scenario() {
+        let nested = "[".repeat(100_000);
+        assert_eq!(Environment::from_scenario_json(&nested).err(),
+                   Some(ScenarioError::Syntax(64)));
+        let shallow = format!("{}{}", "[".repeat(64), "]".repeat(64));
+        assert!(super::parse(&shallow).is_ok());
+    }
+}
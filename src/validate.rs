@@ -0,0 +1,161 @@
+//! Checks that an environment is well-formed.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::{ContainerId, Environment, GrabberId};
+
+/// Describes why an environment is not well-formed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The number of grabber states or pending volumes does not match.
+    StateMismatch,
+    /// A container has a negative or non-finite volume.
+    InvalidVolume(ContainerId),
+    /// A container holds more than its capacity.
+    OverCapacity(ContainerId),
+    /// A container has a negative or non-finite leak rate.
+    InvalidLeakRate(ContainerId),
+    /// A grabber refers to a container that does not exist.
+    UnknownContainer(GrabberId),
+    /// A grabber has a negative or non-finite parameter.
+    InvalidGrabber(GrabberId),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::StateMismatch =>
+                write!(f, "Simulation state does not match the model"),
+            ValidationError::InvalidVolume(c) =>
+                write!(f, "Container {} has an invalid volume", c.0),
+            ValidationError::OverCapacity(c) =>
+                write!(f, "Container {} holds more than its capacity", c.0),
+            ValidationError::InvalidLeakRate(c) =>
+                write!(f, "Container {} has an invalid leak rate", c.0),
+            ValidationError::UnknownContainer(g) =>
+                write!(f, "Grabber {} refers to a container that does not exist", g.0),
+            ValidationError::InvalidGrabber(g) =>
+                write!(f, "Grabber {} has an invalid parameter", g.0),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ValidationError {}
+
+/// Returns whether a value is finite and not negative.
+fn non_negative(v: f64) -> bool {v.is_finite() && v >= 0.0}
+
+impl Environment {
+    /// Checks that the environment is well-formed.
+    ///
+    /// Since the fields of containers and grabbers are public,
+    /// this catches values that the simulation does not handle, e.g. negative volumes.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.grabber_states.len() != self.grabbers.len() ||
+           self.pending.len() != self.containers.len() ||
+           self.removed.len() != self.containers.len() ||
+           self.peaks.len() != self.containers.len() ||
+           self.sunk.len() != self.containers.len()
+        {
+            return Err(ValidationError::StateMismatch);
+        }
+        for (i, c) in self.containers.iter().enumerate() {
+            let id = ContainerId(i);
+            if !non_negative(c.volume) || !non_negative(self.pending[i]) {
+                return Err(ValidationError::InvalidVolume(id));
+            }
+            if c.volume > c.capacity {return Err(ValidationError::OverCapacity(id))}
+            if !non_negative(c.leak_rate) {return Err(ValidationError::InvalidLeakRate(id))}
+        }
+        let n = self.containers.len();
+        for (i, g) in self.grabbers.iter().enumerate() {
+            let id = GrabberId(i);
+            if g.source.0 >= n || g.target.0 >= n ||
+               g.demand_threshold.is_some_and(|(c, _)| c.0 >= n)
+            {
+                return Err(ValidationError::UnknownContainer(id));
+            }
+            let s = &self.grabber_states[i];
+            let valid = [g.volume, g.time, g.jitter, g.cooldown, g.wear_factor, g.min_volume,
+                         s.time, s.volume, s.cooldown]
+                .iter().all(|&v| non_negative(v)) &&
+                (0.0..=1.0).contains(&g.reaction_rate) && g.stages > 0 &&
+                g.ramp > 0.0 && g.ramp <= 1.0 &&
+                g.batch_size.iter().all(|&b| b.is_finite() && b > 0.0);
+            if !valid {return Err(ValidationError::InvalidGrabber(id))}
+        }
+        Ok(())
+    }
+}
+
+impl Environment {
+    /// Panics if the simulation broke an internal invariant during an operation.
+    #[cfg(feature = "debug-invariants")]
+    pub(crate) fn assert_invariants(&self, operation: &str) {
+        assert_eq!(self.grabber_states.len(), self.grabbers.len(),
+                   "`{}` left {} grabber states for {} grabbers",
+                   operation, self.grabber_states.len(), self.grabbers.len());
+        for (i, c) in self.containers.iter().enumerate() {
+            assert!(c.volume >= 0.0,
+                    "`{}` left container {} with negative volume {}", operation, i, c.volume);
+        }
+        for (i, s) in self.grabber_states.iter().enumerate() {
+            assert!(s.time >= 0.0,
+                    "`{}` left grabber {} with negative time {}", operation, i, s.time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_validate() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(10.0));
+        let b = env.add_container(Container::new(0.0).capacity(5.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 2.0, 1.0));
+        assert_eq!(env.validate(), Ok(()));
+        env.containers[b.0].volume = 6.0;
+        assert_eq!(env.validate(), Err(ValidationError::OverCapacity(b)));
+        env.containers[b.0].volume = 0.0;
+        env.containers[a.0].leak_rate = -1.0;
+        assert_eq!(env.validate(), Err(ValidationError::InvalidLeakRate(a)));
+        env.containers[a.0].leak_rate = 0.0;
+        env.grabbers[ab.0].time = -1.0;
+        assert_eq!(env.validate(), Err(ValidationError::InvalidGrabber(ab)));
+        env.grabbers[ab.0].time = 1.0;
+        env.grabbers[ab.0].target = ContainerId(2);
+        assert_eq!(env.validate(), Err(ValidationError::UnknownContainer(ab)));
+    }
+
+    #[test]
+    #[cfg(feature = "debug-invariants")]
+    fn test_invariants() {
+        let mut env = Environment::random_cyclic(6, 20, 11);
+        env.delivery_cap = Some(1.0);
+        for k in 0..20 {
+            for i in 0..20 {
+                if (i + k) % 4 == 0 {let _ = env.grab(GrabberId(i));}
+            }
+            env.update(0.3);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "debug-invariants")]
+    #[should_panic(expected = "negative volume")]
+    fn test_invariants_violated() {
+        let mut env = Environment::new();
+        let a = env.add_container(Container::new(1.0));
+        let b = env.add_container(Container::new(0.0));
+        let ab = env.add_grabber(Grabber::new(a, b, 1.0, 1.0));
+        env.grab(ab).unwrap();
+        env.grabber_states[ab.0].volume = -2.0;
+        env.update(1.0);
+    }
+}